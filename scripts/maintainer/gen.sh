@@ -18,3 +18,7 @@ echo "${t[*]}" # tags are space-delimited
 (IFS=$'\x1f'; echo "${s[*]}")
 (IFS=$'\x1f'; echo "${d[*]}")
 (IFS=$'\x1f'; echo "${kcfg[*]}")
+(IFS=$'\x1f'; echo "${p[*]}")
+(IFS=$'\x1f'; echo "${conflicts[*]}")
+(IFS=$'\x1f'; echo "${replaces[*]}")
+(IFS=$'\x1f'; echo "${bk[*]}")
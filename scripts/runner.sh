@@ -126,9 +126,22 @@ is_dest_populated() {
 if is_dest_populated; then
 
     # Record package manifest and create tarball
+    # Each line is: path, type, mode, uid:gid, size, sha256, and symlink target
     echo "Creating distfile"
-    find "$D" -mindepth 1 |
-        sed -e "s,^$D/,," -e '/^MANIFEST/d' > "$D/MANIFEST"
+    manifest=$(mktemp)
+    find "$D" -mindepth 1 ! -path "$D/MANIFEST" -printf '%P\t%y\t%#m\t%U:%G\t%s\t%l\n' |
+        while IFS=$'\t' read -r p y m o s l; do
+            h=-
+            [ "$y" = f ] && h=$(sha256sum < "$D/$p" | cut -d' ' -f1)
+            printf '%s\t%s\t%s\t%s\t%s\t%s\t%s\n' "$p" "$y" "$m" "$o" "$s" "$h" "${l:--}"
+        done > "$manifest"
+    mv "$manifest" "$D/MANIFEST"
+    chmod 644 "$D/MANIFEST"
+
+    # Record the installed size and number of files, excluding directories
+    sizes=$(find "$D" -mindepth 1 ! -type d ! -path "$D/MANIFEST" -printf '%s\n' |
+        awk '{ s += $1; n++ } END { printf "installed=%d\nfiles=%d", s, n }')
+    echo "$sizes" > "$D/SIZE"
 
     cd "$D"
     tar cf - -- * | zstd -f -T0 -19 -o "/pkg.tar.zst" &>/dev/null
@@ -0,0 +1,30 @@
+use std::path::PathBuf;
+
+use clap::Args;
+
+use super::CommandError;
+use crate::package::Package;
+
+/// Record a package as installed from files already on the system
+#[derive(Args, Debug)]
+pub struct Command {
+    /// The package to adopt
+    #[arg(value_name = "PACKAGE")]
+    pub package: String,
+
+    /// A file listing the package's paths, instead of its distfile's MANIFEST
+    #[arg(long, short = 'l', value_name = "FILE")]
+    pub files: Option<PathBuf>,
+
+    /// Adopt even if the package is installed, leaving out missing paths
+    #[arg(long, short)]
+    pub force: bool,
+}
+
+impl Command {
+    pub async fn run(&self) -> Result<(), CommandError> {
+        let pkg = Package::from_s_file(&self.package)?;
+        pkg.adopt(self.files.as_deref(), self.force)?;
+        Ok(())
+    }
+}
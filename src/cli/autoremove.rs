@@ -0,0 +1,81 @@
+use std::collections::HashSet;
+
+use clap::Args;
+use tracing::{
+    error,
+    info,
+};
+
+use super::CommandError;
+use crate::package::{
+    Package,
+    dep::DepKind,
+    installed_packages,
+    remove::{
+        RemoveError,
+        plan_removal,
+    },
+};
+
+/// Remove packages installed as dependencies that are no longer needed
+#[derive(Args, Debug)]
+pub struct Command {
+    /// Only print what would be removed
+    #[arg(long, short = 'n')]
+    pub dry_run: bool,
+
+    /// Whether to suppress messages
+    #[arg(long, short)]
+    pub suppress_messages: bool,
+}
+
+/// # Finds installed dependency packages that no explicitly installed package needs
+pub fn find_orphans() -> Vec<Package> {
+    let (dependencies, explicit): (Vec<_>, Vec<_>) = installed_packages()
+        .into_iter()
+        .partition(Package::is_dependency_install);
+
+    let needed = explicit
+        .iter()
+        .flat_map(|p| p.resolve_deps(DepKind::for_install))
+        .map(|p| p.name)
+        .collect::<HashSet<_>>();
+
+    dependencies
+        .into_iter()
+        .filter(|p| !needed.contains(&p.name))
+        .collect()
+}
+
+impl Command {
+    pub async fn run(&self) -> Result<(), CommandError> {
+        let orphans = plan_removal(find_orphans(), false)?;
+        if orphans.is_empty() {
+            println!("Nothing to remove");
+            return Ok(())
+        }
+
+        if self.dry_run {
+            println!("Would remove:");
+            for pkg in &orphans {
+                println!(" - {pkg:-}");
+            }
+            return Ok(())
+        }
+
+        let mut removed = 0;
+        for pkg in &orphans {
+            match pkg.remove(false, false, self.suppress_messages) {
+                | Ok(()) => removed += 1,
+                | Err(RemoveError::Critical | RemoveError::Core) => {},
+                | Err(e) => {
+                    error!("Failed to remove {pkg:-}: {e}");
+                    return Err(e.into())
+                },
+            }
+        }
+
+        info!("Removed {removed} unneeded package(s)");
+        Ok(())
+    }
+}
@@ -8,8 +8,9 @@ use tracing::{
 
 use super::CommandError;
 use crate::{
+    config::CONFIG,
     package::{
-        all_package_names, build::{get_build_order, BuildError}, Package
+        all_package_names, build::{get_build_order, BuildError}, sandbox::Backend, Package
     },
 };
 
@@ -32,6 +33,10 @@ pub struct Command {
     /// This will dump the order in which all packages would be built if no packages are specified.
     #[arg(long, short = 'o')]
     pub dump_order: bool,
+
+    /// How to enter the build environment, overriding the config
+    #[arg(long, short, value_name = "BACKEND")]
+    pub backend: Option<Backend>,
 }
 
 impl Command {
@@ -60,7 +65,7 @@ impl Command {
         }
 
         for pkg in &pkgs {
-            match pkg.build(self.force) {
+            match pkg.build(self.force, self.backend.unwrap_or(CONFIG.build_backend)) {
                 | Err(BuildError::ShouldntBuild) => {
                     info!(
                         "Not rebuilding {pkg:-}, pass --force or edit its pkgfile to force a rebuild."
@@ -0,0 +1,82 @@
+use std::cmp::Reverse;
+
+use clap::Args;
+use indicatif::HumanBytes;
+use tracing::warn;
+
+use super::CommandError;
+use crate::package::{
+    Package,
+    installed_packages,
+};
+
+/// List installed packages by disk usage
+#[derive(Args, Debug)]
+pub struct Command {
+    /// The package(s) to list, defaulting to every installed package
+    #[arg(value_name = "PACKAGE", num_args=0..)]
+    pub packages: Vec<String>,
+
+    /// Sort by distfile size instead of installed size
+    #[arg(long, short)]
+    pub distfile: bool,
+}
+
+impl Command {
+    pub async fn run(&self) -> Result<(), CommandError> {
+        let pkgs: Vec<Package> = if self.packages.is_empty() {
+            installed_packages()
+        } else {
+            self.packages
+                .iter()
+                .map(|p| Package::from_s_file(p))
+                .collect::<Result<_, _>>()?
+        };
+
+        let mut sized = vec![];
+        for pkg in pkgs {
+            if !pkg.is_installed() {
+                warn!("Skipping {pkg:-} as it's not installed");
+                continue
+            }
+
+            match pkg.installed_sizes() {
+                | Some(sizes) => sized.push((pkg, sizes)),
+                | None => warn!("Sizes aren't recorded for {pkg:-}"),
+            }
+        }
+
+        if self.distfile {
+            sized.sort_by_key(|(_, s)| Reverse(s.distfile));
+        } else {
+            sized.sort_by_key(|(_, s)| Reverse(s.installed));
+        }
+
+        let distfile = |s: Option<u64>| s.map_or("-".to_string(), |s| HumanBytes(s).to_string());
+
+        println!(
+            "\x1b[1m{:>12} {:>8} {:>12}  Package\x1b[0m",
+            "Installed", "Files", "Distfile"
+        );
+        for (pkg, sizes) in &sized {
+            println!(
+                "{:>12} {:>8} {:>12}  {pkg:-}",
+                HumanBytes(sizes.installed).to_string(),
+                sizes.files,
+                distfile(sizes.distfile),
+            );
+        }
+
+        let installed = sized.iter().map(|(_, s)| s.installed).sum::<u64>();
+        let files = sized.iter().map(|(_, s)| s.files).sum::<u64>();
+        let distfiles = sized.iter().filter_map(|(_, s)| s.distfile).sum::<u64>();
+        println!(
+            "\x1b[1m{:>12} {files:>8} {:>12}  Total ({} packages)\x1b[0m",
+            HumanBytes(installed).to_string(),
+            HumanBytes(distfiles).to_string(),
+            sized.len()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+use std::fs::{
+    remove_file,
+    rename,
+};
+
+use clap::Args;
+use tracing::{
+    info,
+    warn,
+};
+
+use super::CommandError;
+use crate::{
+    exec_interactive,
+    package::backup::{
+        new_file,
+        pending_new_files,
+        with_suffix,
+    },
+    utils::prompt::prompt,
+};
+
+/// Review and merge pending .new configuration files
+#[derive(Args, Debug)]
+pub struct Command {
+    /// Only list pending .new files
+    #[arg(long, short)]
+    pub list: bool,
+}
+
+impl Command {
+    pub async fn run(&self) -> Result<(), CommandError> {
+        let pending = pending_new_files();
+        if pending.is_empty() {
+            println!("No pending .new files");
+            return Ok(())
+        }
+
+        if self.list {
+            for (pkg, path) in &pending {
+                println!("{} ({pkg})", new_file(path).display());
+            }
+            return Ok(())
+        }
+
+        for (pkg, path) in &pending {
+            let new = new_file(path);
+            println!("\n\x1b[1m{} ({pkg})\x1b[0m", path.display());
+            exec_interactive!("diff -u --color=always {path:?} {new:?} || true")?;
+
+            loop {
+                match prompt("[u]se new, [k]eep current, [m]erge, [s]kip?", 's')? {
+                    | 'u' => {
+                        rename(&new, path)?;
+                        info!("Replaced {} with the shipped version", path.display());
+                    },
+                    | 'k' => {
+                        remove_file(&new)?;
+                        info!("Kept {}", path.display());
+                    },
+                    | 'm' => {
+                        let merged = with_suffix(path, ".to-merged");
+                        if exec_interactive!("sdiff -o {merged:?} {path:?} {new:?} || [ -s {merged:?} ]")
+                            .is_err()
+                        {
+                            warn!("Merge failed for {}", path.display());
+                            let _ = remove_file(&merged);
+                            continue
+                        }
+
+                        rename(&merged, path)?;
+                        remove_file(&new)?;
+                        info!("Merged {}", path.display());
+                    },
+                    | 's' => {},
+                    | _ => continue,
+                }
+                break
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -3,10 +3,13 @@ use clap::Args;
 use super::CommandError;
 use crate::{
     imply_all,
-    package::Package,
+    package::{
+        Package,
+        index::Index,
+    },
 };
 
-/// Serialize package metadata
+/// Serialize package metadata and rebuild the package index
 #[derive(Args, Debug)]
 pub struct Command {
     /// The packages to generate
@@ -23,6 +26,7 @@ impl Command {
             Package::generate(name)?;
         }
 
+        Index::build()?.write()?;
         Ok(())
     }
 }
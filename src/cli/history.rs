@@ -0,0 +1,49 @@
+use clap::Args;
+
+use super::CommandError;
+use crate::package::actions::read_history;
+
+/// View the history of package actions
+#[derive(Args, Debug)]
+pub struct Command {
+    /// Only show transactions involving these packages
+    #[arg(value_name = "PACKAGE", num_args=0..)]
+    pub packages: Vec<String>,
+
+    /// How many of the most recent transactions to show
+    #[arg(long, short = 'n', value_name = "COUNT", default_value_t = 20)]
+    pub limit: usize,
+}
+
+impl Command {
+    pub async fn run(&self) -> Result<(), CommandError> {
+        let records = read_history()
+            .into_iter()
+            .filter(|r| self.packages.is_empty() || self.packages.contains(&r.package))
+            .collect::<Vec<_>>();
+
+        if records.is_empty() {
+            println!("No history");
+            return Ok(())
+        }
+
+        let transactions = records.chunk_by(|a, b| a.txid == b.txid).collect::<Vec<_>>();
+        let skip = transactions.len().saturating_sub(self.limit);
+
+        for tx in transactions.into_iter().skip(skip) {
+            let first = &tx[0];
+            println!(
+                "\x1b[1m#{} \x1b[0;3m{} \x1b[0m({})",
+                first.txid,
+                httpdate::fmt_http_date(first.systemtime()),
+                first.command,
+            );
+
+            for record in tx {
+                println!("  {record}");
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,10 +1,24 @@
+use std::collections::HashMap;
+
 use clap::Args;
-use tracing::error;
+use glob::Pattern;
+use tracing::{
+    error,
+    warn,
+};
 
 use super::CommandError;
 use crate::{
     imply_all,
-    package::Package,
+    package::{
+        Package,
+        pull::{
+            multipull,
+            remote_sizes,
+        },
+        transaction::Transaction,
+    },
+    utils::prompt::confirm,
 };
 
 /// Install a package from its distfile
@@ -33,6 +47,18 @@ pub struct Command {
     /// The root directory for package installation
     #[arg(long, short)]
     pub root: Option<String>,
+
+    /// Don't ask for confirmation
+    #[arg(long, short)]
+    pub yes: bool,
+
+    /// Only print the plan
+    #[arg(long, short = 'n')]
+    pub dry_run: bool,
+
+    /// Overwrite files owned by other packages if they match this glob
+    #[arg(long, value_name = "GLOB")]
+    pub overwrite: Vec<Pattern>,
 }
 
 impl Command {
@@ -42,20 +68,36 @@ impl Command {
             .map(|p| Package::from_s_file(p))
             .collect::<Result<_, _>>()?;
 
-        if self.no_dependencies {
-            for pkg in &pkgs {
-                pkg.install_no_deps(self.force, self.suppress_messages, self.root.as_deref())
-                    .inspect_err(|e| error!("Failed to install {pkg}: {e}"))?;
-            }
+        let tx = Transaction::plan(&pkgs, self.force, self.full_force, self.no_dependencies)
+            .inspect_err(|e| error!("Failed to plan installation: {e}"))?;
+
+        if tx.is_empty() {
+            println!("Nothing to install");
+            return Ok(())
+        }
+
+        let missing = tx.missing_distfiles();
+        let sizes = if missing.is_empty() {
+            HashMap::new()
+        } else {
+            remote_sizes(&missing)
+                .await
+                .inspect_err(|e| warn!("Failed to fetch download sizes: {e}"))
+                .unwrap_or_default()
+        };
 
+        tx.display(&sizes);
+        if self.dry_run || (!self.yes && !confirm("Proceed?")?) {
             return Ok(())
         }
 
-        for pkg in &pkgs {
-            pkg.install(self.force, self.full_force, self.suppress_messages, self.root.as_deref())
-                .inspect_err(|e| error!("Failed to install {pkg}: {e}"))?;
+        if !missing.is_empty() {
+            multipull(&missing, false)
+                .await
+                .inspect_err(|e| error!("Failed to pull one or more packages: {e}"))?;
         }
 
+        tx.run(self.suppress_messages, self.root.as_deref(), &self.overwrite)?;
         Ok(())
     }
 }
@@ -0,0 +1,53 @@
+use std::process::exit;
+
+use clap::Args;
+use tracing::{
+    error,
+    info,
+};
+
+use super::CommandError;
+use crate::package::{
+    Package,
+    reason::Reason,
+};
+
+/// Change why a package is recorded as installed
+#[derive(Args, Debug)]
+pub struct Command {
+    /// The package(s) to mark
+    #[arg(value_name = "PACKAGE", num_args=1..)]
+    pub packages: Vec<String>,
+
+    /// Mark as explicitly installed
+    #[arg(long, short, conflicts_with = "dep", required_unless_present = "dep")]
+    pub explicit: bool,
+
+    /// Mark as installed as a dependency
+    #[arg(long, short)]
+    pub dep: bool,
+}
+
+impl Command {
+    pub async fn run(&self) -> Result<(), CommandError> {
+        let reason = if self.explicit { Reason::Explicit } else { Reason::Dependency };
+
+        let pkgs: Vec<Package> = self
+            .packages
+            .iter()
+            .map(|p| Package::from_s_file(p))
+            .collect::<Result<_, _>>()?;
+
+        if let Some(pkg) = pkgs.iter().find(|p| !p.is_installed()) {
+            error!("Can't mark {pkg:-} as it's not installed");
+            exit(1)
+        }
+
+        for pkg in &pkgs {
+            pkg.mark(reason)?;
+            info!("Marked {pkg:-} as {reason}");
+        }
+
+        Ok(())
+    }
+}
@@ -37,8 +37,10 @@ use thiserror::Error;
 use crate::{
     package::{
         FormError,
+        adopt::AdoptError,
         build::BuildError,
         generate::GenerateError,
+        index::IndexError,
         install::InstallError,
         lint::LintError,
         prune::PruneError,
@@ -72,6 +74,9 @@ pub enum CommandError {
     #[error("Failed to remove package: {0}")]
     RemoveError(#[from] RemoveError),
 
+    #[error("Failed to adopt package: {0}")]
+    AdoptError(#[from] AdoptError),
+
     #[error("Failed to pull package: {0}")]
     PullError(#[from] DownloadError),
 
@@ -84,6 +89,9 @@ pub enum CommandError {
     #[error("Failed to generate package: {0}")]
     GenerateError(#[from] GenerateError),
 
+    #[error("Failed to build package index: {0}")]
+    IndexError(#[from] IndexError),
+
     #[error("Failed to install package: {0}")]
     InstallError(#[from] InstallError),
 
@@ -101,26 +109,40 @@ pub enum CommandError {
 
     #[error("Missing dependency: {0}")]
     MissingDependency(String),
+
+    #[error("{0} package(s) failed verification")]
+    Verification(usize),
 }
 
 command_boilerplate! {
     Serve,
     Add,
+    Adopt,
     Alias,
+    Autoremove,
     Build,
     Bump,
     Delete,
+    Du,
     Edit,
+    EtcUpdate,
     Generate,
     Lint,
     Health,
+    History,
     Push,
     Data,
     Install,
+    Mark,
+    Owns,
     Prune,
     Pull,
     Remove,
+    Rollback,
     Sync,
+    Untracked,
+    Upgrade,
+    Verify,
     View,
     Vf,
 }
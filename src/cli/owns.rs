@@ -0,0 +1,49 @@
+use std::path::{
+    Path,
+    absolute,
+};
+
+use clap::Args;
+use tracing::info;
+
+use super::CommandError;
+use crate::package::owners::Owners;
+
+/// Find which installed packages own a path
+#[derive(Args, Debug)]
+pub struct Command {
+    /// The path(s) to look up
+    #[arg(value_name = "PATH", num_args=0.., required_unless_present = "rebuild")]
+    pub paths: Vec<String>,
+
+    /// Rebuild the ownership index from the installed manifests first
+    #[arg(long)]
+    pub rebuild: bool,
+}
+
+impl Command {
+    pub async fn run(&self) -> Result<(), CommandError> {
+        let owners = if self.rebuild {
+            let owners = Owners::build()?;
+            owners.write()?;
+            info!("Rebuilt ownership index with {} paths", owners.paths.len());
+            owners
+        } else {
+            Owners::load()?
+        };
+
+        for path in &self.paths {
+            let path = absolute(Path::new(path))?;
+            let path = path.display();
+            match owners.owners_of(&path.to_string()) {
+                | Some(pkgs) => {
+                    let pkgs = pkgs.iter().map(String::as_str).collect::<Vec<_>>().join(", ");
+                    println!("{path} is owned by {pkgs}");
+                },
+                | None => println!("{path} is not owned by any package"),
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -2,7 +2,13 @@ use clap::Args;
 use tracing::error;
 
 use super::CommandError;
-use crate::package::Package;
+use crate::package::{
+    Package,
+    remove::{
+        plan_removal,
+        unplanned_dependants,
+    },
+};
 
 /// Uninstall a package
 #[derive(Args, Debug)]
@@ -22,6 +28,14 @@ pub struct Command {
     /// Whether to suppress messages
     #[arg(long, short)]
     pub suppress_messages: bool,
+
+    /// Also remove installed packages that depend on the package(s)
+    #[arg(long, short)]
+    pub cascade: bool,
+
+    /// Only print what would be removed
+    #[arg(long, short = 'n')]
+    pub dry_run: bool,
 }
 
 impl Command {
@@ -32,6 +46,22 @@ impl Command {
             .map(|p| Package::from_s_file(p))
             .collect::<Result<_, _>>()?;
 
+        let pkgs = plan_removal(pkgs, self.cascade)?;
+
+        if self.dry_run {
+            println!("Would remove:");
+            for pkg in &pkgs {
+                let broken = unplanned_dependants(pkg, &pkgs)?;
+
+                if broken.is_empty() || self.force {
+                    println!(" - {pkg:-}");
+                } else {
+                    println!(" - {pkg:-} \x1b[31;1m(refused, needed by {})\x1b[0m", broken.join(", "));
+                }
+            }
+            return Ok(())
+        }
+
         for pkg in &pkgs {
             pkg.remove(self.force, self.remove_critical, self.suppress_messages)
                 .inspect_err(|e| error!("Failed to remove {pkg:-}: {e}"))?;
@@ -0,0 +1,95 @@
+use std::process::exit;
+
+use clap::Args;
+use tracing::{
+    error,
+    info,
+    warn,
+};
+
+use super::CommandError;
+use crate::package::{
+    Package,
+    actions::{
+        Action,
+        read_history,
+    },
+};
+
+/// Undo a transaction from the history
+///
+/// Installed packages are removed, and updated or removed packages are reinstalled from their
+/// previous distfiles.
+#[derive(Args, Debug)]
+pub struct Command {
+    /// The transaction id, as shown by `to history`
+    #[arg(value_name = "TXID")]
+    pub txid: u64,
+
+    /// Whether to suppress messages
+    #[arg(long, short)]
+    pub suppress_messages: bool,
+}
+
+impl Command {
+    pub async fn run(&self) -> Result<(), CommandError> {
+        let records = read_history()
+            .into_iter()
+            .filter(|r| r.txid == self.txid && r.action != Action::Build)
+            .collect::<Vec<_>>();
+
+        if records.is_empty() {
+            error!("No actions to roll back for transaction #{}", self.txid);
+            exit(1)
+        }
+
+        // Form every package first, so nothing is done unless the whole transaction can be undone
+        let mut plan = Vec::new();
+        for record in records.iter().rev() {
+            let mut pkg = Package::from_s_file(&record.package)?;
+            if let Some(old) = &record.old {
+                pkg.version = old.clone();
+                if !pkg.distfile().exists() {
+                    error!("Can't roll back {}: missing distfile {}", record.package, pkg.distfile().display());
+                    exit(1)
+                }
+            }
+
+            plan.push((record, pkg));
+        }
+
+        for (record, pkg) in plan {
+            let root = Some(record.root.as_str()).filter(|r| *r != "/");
+            match (record.action, &record.old) {
+                | (Action::Install, _) => {
+                    if root.is_some() {
+                        warn!("Not removing {pkg:-} from {}, since removal only supports /", record.root);
+                        continue
+                    }
+
+                    info!("Rolling back install of {pkg:-}");
+                    pkg.remove(false, false, self.suppress_messages)
+                        .inspect_err(|e| error!("Failed to remove {pkg:-}: {e}"))?;
+                },
+                | (_, None) => warn!("Nothing to roll back to for {}", record.package),
+                | (_, Some(_)) => {
+                    info!("Rolling back {} of {} to {pkg:-}", record.action, record.package);
+                    let result = pkg.install_no_deps(true, self.suppress_messages, root);
+
+                    // Removed packages get back the reason they were installed for, even if a
+                    // later step failed, so they aren't left marked as dependencies
+                    if let Some(reason) = record.reason
+                        && pkg.is_installed()
+                    {
+                        pkg.mark(reason)?;
+                    }
+
+                    result.inspect_err(|e| error!("Failed to reinstall {pkg:-}: {e}"))?;
+                },
+            }
+        }
+
+        info!("Rolled back transaction #{}", self.txid);
+        Ok(())
+    }
+}
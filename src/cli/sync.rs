@@ -7,10 +7,11 @@ use super::CommandError;
 use crate::{
     config::CONFIG,
     exec,
+    package::index::Index,
     utils::file::exists,
 };
 
-/// Sync the local package repository with its remote
+/// Sync the local package repository with its remote and rebuild the package index
 #[derive(Args, Debug)]
 pub struct Command {
     /// The branch to sync
@@ -47,6 +48,7 @@ impl Command {
             exit(1)
         }
 
+        Index::build()?.write()?;
         Ok(())
     }
 }
@@ -0,0 +1,49 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+};
+
+use clap::Args;
+use tracing::info;
+
+use super::CommandError;
+use crate::package::owners::find_untracked;
+
+/// List files no installed package owns
+#[derive(Args, Debug)]
+pub struct Command {
+    /// Only look under this directory
+    #[arg(long, short, value_name = "DIR", default_value = "/")]
+    pub under: PathBuf,
+
+    /// Group files by directory
+    #[arg(long, short)]
+    pub group: bool,
+}
+
+impl Command {
+    pub async fn run(&self) -> Result<(), CommandError> {
+        let untracked = find_untracked(&self.under)?;
+
+        if self.group {
+            let mut groups = BTreeMap::<_, Vec<_>>::new();
+            for path in &untracked {
+                groups.entry(path.parent().unwrap_or(&self.under)).or_default().push(path);
+            }
+
+            for (dir, paths) in groups {
+                println!("\x1b[1m{}\x1b[0m ({})", dir.display(), paths.len());
+                for path in paths {
+                    println!("  {}", path.file_name().unwrap_or_default().to_string_lossy());
+                }
+            }
+        } else {
+            for path in &untracked {
+                println!("{}", path.display());
+            }
+        }
+
+        info!("Found {} untracked file(s) under {}", untracked.len(), self.under.display());
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::HashSet;
+
+use clap::Args;
+use permitit::Permit;
+use tracing::{
+    error,
+    info,
+};
+
+use super::CommandError;
+use crate::package::{
+    Package,
+    dep::DepKind,
+    install::InstallError,
+    installed_packages,
+    message::MessageHook,
+    pull::multipull,
+    trigger::run_triggers,
+};
+
+/// Upgrade every outdated installed package
+#[derive(Args, Debug)]
+pub struct Command {
+    /// Packages to leave at their installed versions
+    #[arg(long, short = 'x', value_name = "PACKAGE", value_delimiter = ',')]
+    pub exclude: Vec<String>,
+
+    /// Only print what would be upgraded
+    #[arg(long, short = 'n')]
+    pub dry_run: bool,
+
+    /// Whether to suppress messages
+    #[arg(long, short)]
+    pub suppress_messages: bool,
+}
+
+/// # Plans an upgrade, returning the packages to install in dependency order
+///
+/// Outdated packages are installed packages whose installed version differs from the repository.
+/// Dependencies of outdated packages that aren't installed yet are included before their
+/// dependants.
+pub fn plan_upgrade(exclude: &[String]) -> Vec<Package> {
+    let outdated = installed_packages()
+        .into_iter()
+        .filter(|p| !p.is_current() && !exclude.contains(&p.name))
+        .collect::<Vec<_>>();
+
+    let mut seen = HashSet::new();
+    let mut plan = Vec::new();
+    for pkg in &outdated {
+        let deps = pkg
+            .resolve_deps(DepKind::for_install)
+            .into_iter()
+            .filter(|d| !d.is_installed() || outdated.iter().any(|o| o.name == d.name));
+
+        for p in deps.chain([pkg.clone()]) {
+            if seen.insert(p.name.clone()) {
+                plan.push(p);
+            }
+        }
+    }
+
+    plan
+}
+
+impl Command {
+    pub async fn run(&self) -> Result<(), CommandError> {
+        let plan = plan_upgrade(&self.exclude);
+        if plan.is_empty() {
+            println!("Everything is up to date");
+            return Ok(())
+        }
+
+        if self.dry_run {
+            println!("Would upgrade:");
+            for pkg in &plan {
+                println!("{pkg:+}");
+            }
+            return Ok(())
+        }
+
+        let missing = plan
+            .iter()
+            .filter(|p| !p.distfile().exists())
+            .cloned()
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            multipull(&missing, false)
+                .await
+                .inspect_err(|e| error!("Failed to pull one or more packages: {e}"))?;
+        }
+
+        // Excluded and planned packages start as visited, so `install_inner()` neither upgrades an
+        // excluded dependency nor installs a planned one out of order
+        let mut visited = self
+            .exclude
+            .iter()
+            .cloned()
+            .chain(plan.iter().map(|p| p.name.clone()))
+            .collect::<HashSet<_>>();
+
+        // Messages are suppressed during installation and shown together afterwards
+        let mut done = Vec::new();
+        let mut result = Ok(());
+        for pkg in &plan {
+            let hook = if pkg.is_installed() { MessageHook::Update } else { MessageHook::Install };
+            visited.remove(&pkg.name);
+            if let Err(e) = pkg
+                .install_inner(false, false, &mut visited, true, None, &[])
+                .permit(|e| matches!(e, InstallError::AlreadyInstalled))
+            {
+                error!("Failed to upgrade {pkg:-}: {e}");
+                result = Err(e.into());
+                break
+            }
+            done.push((pkg, hook));
+        }
+
+        run_triggers(done.iter().map(|(pkg, _)| *pkg), None);
+
+        info!("Upgraded {} of {} package(s)", done.len(), plan.len());
+        for (pkg, hook) in done {
+            pkg.message(self.suppress_messages, hook);
+        }
+
+        result
+    }
+}
@@ -0,0 +1,65 @@
+use std::path::Path;
+
+use clap::Args;
+use tracing::{
+    error,
+    info,
+    warn,
+};
+
+use super::CommandError;
+use crate::package::{
+    Package,
+    installed_packages,
+};
+
+/// Check installed files against their packages' manifests
+#[derive(Args, Debug)]
+pub struct Command {
+    /// The package(s) to verify, defaulting to every installed package
+    #[arg(value_name = "PACKAGE", num_args=0..)]
+    pub packages: Vec<String>,
+}
+
+impl Command {
+    pub async fn run(&self) -> Result<(), CommandError> {
+        let pkgs: Vec<Package> = if self.packages.is_empty() {
+            installed_packages()
+        } else {
+            self.packages
+                .iter()
+                .map(|p| Package::from_s_file(p))
+                .collect::<Result<_, _>>()?
+        };
+
+        let mut failed = 0;
+        for pkg in &pkgs {
+            if !pkg.is_installed() {
+                warn!("Not verifying {pkg:-} as it's not installed");
+                continue
+            }
+
+            let problems = pkg
+                .verify(Path::new("/"))
+                .inspect_err(|e| error!("Failed to verify {pkg:-}: {e}"))?;
+            if problems.is_empty() {
+                continue
+            }
+
+            failed += 1;
+            println!("\x1b[1m{pkg:-}\x1b[0m");
+            for (entry, problems) in problems {
+                for problem in problems {
+                    println!("  /{} {problem}", entry.path);
+                }
+            }
+        }
+
+        if failed > 0 {
+            return Err(CommandError::Verification(failed))
+        }
+
+        info!("Verified {} package(s)", pkgs.len());
+        Ok(())
+    }
+}
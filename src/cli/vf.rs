@@ -57,7 +57,7 @@ impl Command {
         }
 
         for vf in vfs {
-            if vf.is_current && self.outdated_only {
+            if (vf.is_current || vf.is_ahead) && self.outdated_only {
                 continue
             }
 
@@ -7,7 +7,11 @@ use super::CommandError;
 use crate::{
     config::CONFIG,
     imply_all,
-    package::Package,
+    package::{
+        Package,
+        dep::DepKind,
+        provides::is_virtual,
+    },
 };
 
 /// View information about a package
@@ -35,10 +39,18 @@ pub struct Command {
     #[arg(long, short = 'D')]
     pub dependants: bool,
 
-    /// Show deep dependencies
+    /// Show deep dependencies, or with `--dependants`, deep dependants
     #[arg(long, short = '!')]
     pub deep: bool,
 
+    /// With `--dependants --deep`, only follow these dependency kinds
+    #[arg(long, short = 'k', value_name = "KIND", value_delimiter = ',')]
+    pub kinds: Vec<DepKind>,
+
+    /// With `--dependants --deep`, only follow installed dependants
+    #[arg(long, short = 'i')]
+    pub installed: bool,
+
     /// View the filetree of a package's distfile
     #[arg(long, short = 'T')]
     pub tree: bool,
@@ -58,7 +70,14 @@ pub struct Command {
 
 impl Command {
     pub async fn run(&self) -> Result<(), CommandError> {
-        let pkgs: Vec<Package> = imply_all!(self)
+        let (virtuals, names): (Vec<_>, Vec<_>) =
+            imply_all!(self).into_iter().partition(|p| is_virtual(p));
+
+        for name in &virtuals {
+            Package::view_providers(name);
+        }
+
+        let pkgs: Vec<Package> = names
             .iter()
             .map(|p| Package::from_s_file(p))
             .collect::<Result<_, _>>()?;
@@ -91,7 +110,7 @@ impl Command {
 
             if self.dependants {
                 if self.deep {
-                    todo!("Deep dependants");
+                    pkg.view_deep_dependants(&self.kinds, self.installed);
                 } else {
                     pkg.view_dependants();
                 }
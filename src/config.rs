@@ -4,12 +4,18 @@
 // TODO: Once there are enough configure options, organize them into structs
 
 use std::{
+    collections::HashMap,
     fs,
     sync::LazyLock,
 };
 
 use serde::Deserialize;
 
+use crate::package::{
+    sandbox::Backend,
+    trigger::Trigger,
+};
+
 pub static CONFIG: LazyLock<Config> = LazyLock::new(Config::load);
 
 #[derive(Debug, Deserialize)]
@@ -27,6 +33,8 @@ pub struct Config {
     pub makeflags:           String,
     /// Stagefile to use for the build environment
     pub stagefile:           String,
+    /// Backend used to enter the build environment (auto, namespace, or chroot)
+    pub build_backend:       Backend,
     /// CFLAGS, CXXFLAGS, FFLAGS, and FCFLAGS to pass to the build environment
     pub cflags:              String,
     /// RUSTFLAGS to pass to the build environment
@@ -39,6 +47,14 @@ pub struct Config {
     pub package_repo:        String,
     /// Branch for the package repository
     pub package_repo_branch: String,
+    /// Preferred providers for virtual packages, used when no provider is installed
+    pub providers:           HashMap<String, String>,
+    /// Whether to protect every file under /etc, in addition to those in a package's `bk=()`
+    pub protect_etc:         bool,
+    /// Commands run once per transaction when installed packages ship matching paths
+    pub triggers:            Vec<Trigger>,
+    /// Globs for paths, relative to /, that `to untracked` skips
+    pub untracked_ignore:    Vec<String>,
 }
 
 impl Default for Config {
@@ -50,12 +66,22 @@ impl Default for Config {
             tests:               false,
             makeflags:           format!("-j{}", num_cpus::get()),
             stagefile:           "/usr/share/to/stagefile.tar.xz".to_string(),
+            build_backend:       Backend::Auto,
             cflags:              "-march=x86-64-v3 -O2 -pipe".to_string(),
             rustflags:           "-C opt-level=2 -C target-cpu=x86-64-v3".to_string(),
             tree_command:        "tree -F".to_string(),
             server_address:      "127.0.0.1:7020".to_string(),
             package_repo:        "https://github.com/Toxikuu/to-pkgs.git".to_string(),
             package_repo_branch: "master".to_string(),
+            providers:           HashMap::new(),
+            protect_etc:         true,
+            triggers:            Trigger::defaults(),
+            untracked_ignore:    [
+                "proc", "sys", "dev", "run", "tmp", "home", "root", "mnt", "media", "lost+found",
+                "var/db/to", "var/cache", "var/log", "var/tmp", "**/__pycache__",
+            ]
+            .map(String::from)
+            .to_vec(),
         }
     }
 }
@@ -1,60 +1,190 @@
 // package/actions.rs
-//! Code related to logging package actions (removal, installs, builds, edits)
+//! Code related to logging package actions (installs, updates, removals, builds)
+//!
+//! The current action is written to `/var/log/to/current`. Every action is also recorded in
+//! `/var/log/to/history`, as JSON lines. Records from the same invocation of `to` share a
+//! transaction id, which `to rollback` uses to undo them.
 
-// TODO: Documentation
-
-use std::path::PathBuf;
+use std::{
+    env,
+    fmt,
+    fs::read_to_string,
+    path::PathBuf,
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
 
 use once_cell::sync::Lazy;
+use serde::{
+    Deserialize,
+    Serialize,
+};
 use tracing::warn;
 
-use super::Package;
+use super::{
+    Package,
+    Version,
+    reason::Reason,
+};
 use crate::utils::file::{
     append,
     overwrite,
 };
 
 static CURRENT: Lazy<PathBuf> = Lazy::new(|| PathBuf::from("/var/log/to/current"));
-static ALLTIME: Lazy<PathBuf> = Lazy::new(|| PathBuf::from("/var/log/to/alltime"));
+pub static HISTORY: Lazy<PathBuf> = Lazy::new(|| PathBuf::from("/var/log/to/history"));
 
-impl Package {
-    pub fn log_installing(&self) {
-        if let Err(e) = overwrite(&*CURRENT, format!("{self:<32} ::: INSTALL\n")) {
-            warn!("Failed to log current action for {self}: {e}")
-        }
+/// The transaction id for this invocation, one greater than the last recorded
+static TXID: Lazy<u64> = Lazy::new(|| read_history().last().map_or(1, |r| r.txid + 1));
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Install,
+    Update,
+    Remove,
+    Build,
+}
 
-        if let Err(e) = append(&*ALLTIME, format!("{self:<32} ::: INSTALL\n")) {
-            warn!("Failed to log alltime action for {self}: {e}")
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Self::Install => f.pad("install"),
+            | Self::Update => f.pad("update"),
+            | Self::Remove => f.pad("remove"),
+            | Self::Build => f.pad("build"),
         }
     }
+}
+
+/// # A record of a package action
+///
+/// # Fields
+/// * `txid`            - The transaction id, shared by records from the same invocation
+/// * `time`            - Seconds since the unix epoch
+/// * `old`             - The version before the action, if any
+/// * `new`             - The version after the action, if any
+/// * `root`            - The root the action applied to
+/// * `command`         - The command line that requested the action
+/// * `reason`          - Why a removed package was installed, so rolling back can restore it
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Record {
+    pub txid:    u64,
+    pub time:    u64,
+    pub action:  Action,
+    pub package: String,
+    pub old:     Option<Version>,
+    pub new:     Option<Version>,
+    pub root:    String,
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason:  Option<Reason>,
+}
+
+impl Record {
+    pub fn systemtime(&self) -> SystemTime { UNIX_EPOCH + Duration::from_secs(self.time) }
+}
 
-    pub fn log_removing(&self) {
-        if let Err(e) = overwrite(&*ALLTIME, format!("{self:<32} ::: REMOVE\n")) {
-            warn!("Failed to log action for {self}: {e}")
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let version = |v: &Option<Version>| v.as_ref().map_or("none".to_string(), Version::srversion);
+        write!(f, "{:<8} {}", self.action, self.package)?;
+        match self.action {
+            | Action::Update => write!(f, " {} -> {}", version(&self.old), version(&self.new))?,
+            | Action::Remove => write!(f, " {}", version(&self.old))?,
+            | Action::Install | Action::Build => write!(f, " {}", version(&self.new))?,
         }
 
-        if let Err(e) = append(&*ALLTIME, format!("{self:<32} ::: REMOVE\n")) {
-            warn!("Failed to log alltime action for {self}: {e}")
+        if self.root != "/" {
+            write!(f, " (root: {})", self.root)?;
         }
+
+        Ok(())
     }
+}
 
-    pub fn log_building(&self) {
-        if let Err(e) = overwrite(&*CURRENT, format!("{self:<32} ::: BUILD\n")) {
-            warn!("Failed to log action for {self}: {e}")
+/// # Reads every record in the history, skipping invalid lines
+pub fn read_history() -> Vec<Record> {
+    let Ok(contents) = read_to_string(&*HISTORY) else {
+        return vec![]
+    };
+
+    contents
+        .lines()
+        .filter_map(|l| {
+            serde_json::from_str(l)
+                .inspect_err(|e| warn!("Skipping invalid history record: {e}"))
+                .ok()
+        })
+        .collect()
+}
+
+impl Package {
+    fn log_action(
+        &self,
+        action: Action,
+        old: Option<Version>,
+        new: Option<Version>,
+        root: Option<&str>,
+        reason: Option<Reason>,
+    ) {
+        if let Err(e) = overwrite(&*CURRENT, format!("{self:<32} ::: {action}\n")) {
+            warn!("Failed to log current action for {self}: {e}")
         }
 
-        if let Err(e) = append(&*ALLTIME, format!("{self:<32} ::: BUILD\n")) {
-            warn!("Failed to log alltime action for {self}: {e}")
+        let record = Record {
+            txid: *TXID,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            action,
+            package: self.name.clone(),
+            old,
+            new,
+            root: root.unwrap_or("/").to_string(),
+            command: env::args().collect::<Vec<_>>().join(" "),
+            reason,
+        };
+
+        let line = match serde_json::to_string(&record) {
+            | Ok(l) => l,
+            | Err(e) => return warn!("Failed to serialize history record for {self}: {e}"),
+        };
+
+        if let Err(e) = append(&*HISTORY, format!("{line}\n")) {
+            warn!("Failed to record history for {self}: {e}")
         }
     }
 
-    pub fn log_editing(&self) {
-        if let Err(e) = overwrite(&*CURRENT, format!("{self:<32} ::: EDIT\n")) {
-            warn!("Failed to log action for {self}: {e}")
-        }
+    /// # Logs an install, or an update if a version was previously installed
+    pub fn log_installing(&self, old: Option<Version>, root: Option<&str>) {
+        let action = if old.is_some() { Action::Update } else { Action::Install };
+        self.log_action(action, old, Some(self.version.clone()), root, None)
+    }
 
-        if let Err(e) = append(&*ALLTIME, format!("{self:<32} ::: EDIT\n")) {
-            warn!("Failed to log alltime action for {self}: {e}")
-        }
+    pub fn log_removing(&self, old: Option<Version>, reason: Option<Reason>) {
+        self.log_action(Action::Remove, old, None, None, reason)
+    }
+
+    pub fn log_building(&self) {
+        self.log_action(Action::Build, None, Some(self.version.clone()), None, None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn record_roundtrip() {
+        let line = r#"{"txid":3,"time":0,"action":"update","package":"glibc","old":{"version":"2.40","release":1},"new":{"version":"2.41","release":2},"root":"/","command":"to install glibc"}"#;
+        let record: Record = serde_json::from_str(line).unwrap();
+
+        assert_eq!(record.action, Action::Update);
+        assert_eq!(record.to_string(), "update   glibc 2.40-1 -> 2.41-2");
+        assert_eq!(serde_json::to_string(&record).unwrap(), line);
     }
 }
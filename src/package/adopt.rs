@@ -0,0 +1,152 @@
+// package/adopt.rs
+//! Adopting existing files into the package database
+//!
+//! Software built by hand, like an LFS base system, can be recorded as installed without
+//! reinstalling it. The package's paths are taken from its distfile's MANIFEST or a supplied file
+//! list, checked against the filesystem, and recorded in a manifest describing the files as they
+//! exist. Nothing is extracted.
+
+use std::{
+    fs::{
+        self,
+        read_to_string,
+    },
+    io,
+    path::Path,
+};
+
+use fshelpers::mkdir_p;
+use thiserror::Error;
+use tracing::{
+    error,
+    info,
+    warn,
+};
+
+use super::{
+    Package,
+    manifest::{
+        Entry,
+        path_of,
+    },
+    reason::Reason,
+    size::Sizes,
+};
+
+#[derive(Debug, Error)]
+pub enum AdoptError {
+    #[error("Package is already installed")]
+    AlreadyInstalled,
+
+    #[error("{0} path(s) are missing")]
+    Missing(usize),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// # Parses a file list, with one path per line
+///
+/// Paths may be absolute or relative to the root. Blank lines and lines starting with `#` are
+/// skipped, and manifest records are accepted.
+fn parse_file_list(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(|l| path_of(l).trim().trim_matches('/'))
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+impl Package {
+    /// # Records a package as installed from files already on the system
+    ///
+    /// # Arguments
+    /// * `files`           - A file list to use instead of the distfile's MANIFEST
+    /// * `force`           - Whether to adopt an installed package, or leave out missing paths
+    ///
+    /// # Errors
+    /// - The package is already installed
+    /// - Some paths are missing
+    /// - The paths couldn't be read, or the manifest couldn't be written
+    pub fn adopt(&self, files: Option<&Path>, force: bool) -> Result<(), AdoptError> {
+        if self.is_installed() && !force {
+            error!("{self:-} is already installed");
+            return Err(AdoptError::AlreadyInstalled)
+        }
+
+        let paths = match files {
+            | Some(f) => parse_file_list(&read_to_string(f)?),
+            | None => self
+                .distfile_manifest()
+                .inspect_err(|e| error!("Failed to read the MANIFEST for {self:-}: {e}"))?,
+        };
+
+        let (present, missing): (Vec<_>, Vec<_>) = paths
+            .into_iter()
+            .partition(|p| Path::new("/").join(p).symlink_metadata().is_ok());
+
+        if !missing.is_empty() {
+            for path in &missing {
+                warn!("Missing /{path}");
+            }
+
+            if !force {
+                error!(
+                    "Not adopting {self:-} as {} path(s) are missing",
+                    missing.len()
+                );
+                error!("To adopt it without them, pass --force");
+                return Err(AdoptError::Missing(missing.len()))
+            }
+        }
+
+        // Protected files may have been modified, so their hashes aren't recorded as shipped
+        let entries = present
+            .iter()
+            .map(|p| {
+                let mut entry = Entry::from_path(Path::new("/"), p)?;
+                if self.is_protected(p)
+                    && let Some(r) = &mut entry.record
+                {
+                    r.sha256 = None;
+                }
+                Ok(entry)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let manifest = entries.iter().map(|e| format!("{e}\n")).collect::<String>();
+
+        let data = self.datadir();
+        mkdir_p(&data)?;
+        fs::write(
+            data.join(format!("MANIFEST@{}", self.version.srversion())),
+            manifest,
+        )?;
+        fs::write(data.join("IV"), self.version.rversion())?;
+        if let Some(sizes) = Sizes::from_entries(&entries) {
+            fs::write(data.join("SIZE"), sizes.to_string())?;
+        }
+
+        self.record_owned();
+        self.mark(Reason::Explicit)?;
+        self.log_installing(None, None);
+
+        info!("Adopted {self:-} with {} path(s)", present.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn file_lists() {
+        let list = "# coreutils\n/usr/bin/ls\nusr/bin/cat\n\nusr/bin/cp\tf\t0755\t0:0\t1\t-\t-\n";
+        assert_eq!(parse_file_list(list), [
+            "usr/bin/ls",
+            "usr/bin/cat",
+            "usr/bin/cp"
+        ]);
+    }
+}
@@ -0,0 +1,305 @@
+// package/backup.rs
+//! Protected configuration files
+//!
+//! Files listed in a package's `bk=()`, and by default every file under `etc/`, are protected.
+//! The hashes of protected files as shipped are taken from the package's installed manifest.
+//!
+//! Before extraction, protected files whose contents differ from the recorded hash (meaning the
+//! user modified them) are moved aside. After extraction, the user's file is put back. If the
+//! package also changed the file, the shipped version is kept next to it as a `.new` file, to be
+//! reviewed with `to etc-update`.
+
+use std::{
+    collections::BTreeMap,
+    fs::{
+        self,
+        rename,
+    },
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use tracing::{
+    debug,
+    error,
+    warn,
+};
+
+use super::{
+    Package,
+    installed_packages,
+    manifest::path_of,
+};
+use crate::{
+    CONFIG,
+    sex,
+    utils::file::sha256,
+};
+
+/// The suffix for shipped versions of modified protected files
+pub const NEW_SUFFIX: &str = ".new";
+
+/// The suffix for modified protected files while they're moved aside during extraction
+const HELD_SUFFIX: &str = ".to-held";
+
+/// # Appends a suffix to a path, keeping any extension it has
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// # A protected file the user modified, moved aside during extraction
+#[derive(Debug)]
+struct Held {
+    path:    String,
+    hash:    String,
+    current: PathBuf,
+    held:    PathBuf,
+}
+
+/// # The protected files of a package being installed
+///
+/// Created by `Package::hold_protected()` before extraction, and settled with `settle()` after a
+/// successful extraction. If extraction or anything after it fails, even settling, `restore()`
+/// undoes it.
+#[must_use]
+#[derive(Debug)]
+pub struct Protected {
+    held: Vec<Held>,
+    new:  Vec<PathBuf>,
+}
+
+/// # Checks whether a shipped file should be kept as a `.new` file next to a modified one
+///
+/// That's the case unless the shipped file matches the user's file, or the package didn't change
+/// the file since it was last shipped.
+fn needs_new(modified: &str, shipped: &str, recorded: Option<&String>) -> bool {
+    shipped != modified && recorded.is_none_or(|r| r != shipped)
+}
+
+impl Package {
+    /// # Checks whether a path, relative to the root, is protected
+    pub fn is_protected(&self, path: &str) -> bool {
+        let path = path.trim_start_matches('/');
+        self.backup.iter().any(|b| b.trim_start_matches('/') == path)
+            || (CONFIG.protect_etc && path.starts_with("etc/"))
+    }
+
+    /// # Reads the hashes of a package's protected files as last shipped, keyed by path
+    ///
+    /// The hashes come from the installed manifest. Files without one, as in older manifests, are
+    /// treated as modified.
+    pub fn recorded_hashes(&self) -> BTreeMap<String, String> {
+        self.manifest_entries()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|e| self.is_protected(&e.path))
+            .filter_map(|e| Some((e.path, e.record?.sha256?)))
+            .collect()
+    }
+
+    /// # Lists the paths in a package's distfile, from its MANIFEST
+    pub fn distfile_manifest(&self) -> io::Result<Vec<String>> {
+        let manifest = sex!("tar xOf '{}' MANIFEST", self.distfile().display())?;
+        Ok(manifest.lines().map(|l| path_of(l).to_string()).collect())
+    }
+
+    /// # Moves aside modified protected files before extraction
+    ///
+    /// # Errors
+    /// - The distfile's MANIFEST couldn't be read
+    /// - A protected file couldn't be hashed or moved
+    pub fn hold_protected(&self, root: &Path) -> io::Result<Protected> {
+        let recorded = self.recorded_hashes();
+        let mut protected = Protected {
+            held: Vec::new(),
+            new:  Vec::new(),
+        };
+
+        for path in self.distfile_manifest()?.into_iter().filter(|p| self.is_protected(p)) {
+            let current = root.join(&path);
+            if current.symlink_metadata().is_ok_and(|m| m.is_file()) {
+                let hash = sha256(&current)?;
+                if recorded.get(&path) != Some(&hash) {
+                    let held = with_suffix(&current, HELD_SUFFIX);
+                    debug!("Holding modified protected file {}", current.display());
+                    if let Err(e) = rename(&current, &held) {
+                        protected.restore();
+                        return Err(e)
+                    }
+
+                    protected.held.push(Held {
+                        path,
+                        hash,
+                        current,
+                        held,
+                    });
+                }
+            }
+        }
+
+        Ok(protected)
+    }
+}
+
+impl Protected {
+    /// # Returns the absolute paths of held files
+    pub fn held(&self) -> impl Iterator<Item = &Path> { self.held.iter().map(|h| h.current.as_path()) }
+
+    /// # Puts held files back, discarding whatever was extracted over them
+    ///
+    /// `.new` files written by `settle()` are removed.
+    pub fn restore(self) {
+        for h in self.held {
+            if let Err(e) = rename(&h.held, &h.current) {
+                error!("Failed to restore {}: {e}", h.current.display());
+                error!("Your version is at {}", h.held.display());
+            }
+        }
+
+        for new in self.new {
+            if let Err(e) = fs::remove_file(&new) {
+                warn!("Failed to remove {}: {e}", new.display());
+            }
+        }
+    }
+
+    /// # Settles protected files after extraction
+    ///
+    /// Held files are put back. If the shipped file differs from both the user's file and the
+    /// previously shipped file, it's kept as a `.new` file. This must run before the new manifest
+    /// is installed, since the previously shipped hashes are read from the old one.
+    ///
+    /// Settled files are tracked, so `restore()` can undo settling even if it fails partway.
+    ///
+    /// # Errors
+    /// - A protected file couldn't be hashed or moved
+    pub fn settle(&mut self, pkg: &Package) -> io::Result<()> {
+        let recorded = pkg.recorded_hashes();
+
+        while let Some(h) = self.held.last() {
+            let hash = sha256(&h.current)?;
+            if !needs_new(&h.hash, &hash, recorded.get(&h.path)) {
+                debug!("Keeping {} since the shipped version is unchanged", h.current.display());
+                rename(&h.held, &h.current)?;
+            } else {
+                let new = with_suffix(&h.current, NEW_SUFFIX);
+                rename(&h.current, &new)?;
+                self.new.push(new.clone());
+                rename(&h.held, &h.current)?;
+                warn!("Kept your {}, writing the shipped version to {}", h.current.display(), new.display());
+                warn!("Review it with `to etc-update`");
+            }
+            self.held.pop();
+        }
+
+        Ok(())
+    }
+}
+
+/// # Finds every pending `.new` file for installed packages, as (package, path) pairs
+///
+/// Paths are absolute, and point to the current file rather than the `.new` file.
+pub fn pending_new_files() -> Vec<(String, PathBuf)> {
+    installed_packages()
+        .iter()
+        .flat_map(|pkg| {
+            pkg.manifest_entries()
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|e| pkg.is_protected(&e.path))
+                .map(|e| Path::new("/").join(e.path))
+                .filter(|p| with_suffix(p, NEW_SUFFIX).exists())
+                .map(|p| (pkg.name.clone(), p))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// # Returns the path of a file's `.new` sibling
+pub fn new_file(path: &Path) -> PathBuf { with_suffix(path, NEW_SUFFIX) }
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn new_files() {
+        let old = "old".to_string();
+
+        // Unchanged by the package since it was last shipped
+        assert!(!needs_new("mine", "old", Some(&old)));
+        // Changed by both the package and the user
+        assert!(needs_new("mine", "new", Some(&old)));
+        // Changed by the package the same way the user changed it
+        assert!(!needs_new("new", "new", Some(&old)));
+        // Never recorded, like on a first install over an existing file
+        assert!(needs_new("mine", "new", None));
+    }
+
+    #[test]
+    fn restore_held_files() {
+        let root = tempdir().unwrap();
+        let file = root.path().join("etc/foo.conf");
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+
+        // Simulate a user-modified file held aside, with a changed shipped version extracted
+        fs::write(&file, "shipped\n").unwrap();
+        let held = with_suffix(&file, HELD_SUFFIX);
+        fs::write(&held, "mine\n").unwrap();
+
+        let protected = Protected {
+            new:  vec![],
+            held: vec![Held {
+                path:    "etc/foo.conf".to_string(),
+                hash:    sha256(&held).unwrap(),
+                current: file.clone(),
+                held:    held.clone(),
+            }],
+        };
+
+        // Restoring puts the user's file back
+        protected.restore();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "mine\n");
+        assert!(!held.exists());
+        assert!(!new_file(&file).exists());
+    }
+
+    #[test]
+    fn restore_settled_files() {
+        let root = tempdir().unwrap();
+        let file = root.path().join("foo.conf");
+        fs::write(&file, "shipped\n").unwrap();
+        let held = with_suffix(&file, HELD_SUFFIX);
+        fs::write(&held, "mine\n").unwrap();
+
+        let mut protected = Protected {
+            new:  vec![],
+            held: vec![Held {
+                path:    "foo.conf".to_string(),
+                hash:    sha256(&held).unwrap(),
+                current: file.clone(),
+                held:    held.clone(),
+            }],
+        };
+
+        // Nothing was recorded, so the shipped version is kept as a .new file
+        protected.settle(&Package::stub("to-test-protected", &[])).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "mine\n");
+        assert_eq!(fs::read_to_string(new_file(&file)).unwrap(), "shipped\n");
+
+        // A failure after settling removes the .new file again
+        protected.restore();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "mine\n");
+        assert!(!new_file(&file).exists());
+        assert!(!held.exists());
+    }
+}
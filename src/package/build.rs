@@ -18,6 +18,7 @@ use fshelpers::{
     mkf_p,
     rmdir_r,
 };
+use nix::mount::MsFlags;
 use permitit::Permit;
 use thiserror::Error;
 use tracing::{
@@ -29,15 +30,27 @@ use tracing::{
 
 use super::{
     Package,
+    mounts::{
+        Holder,
+        Mounts,
+        clean_stale_mounts,
+    },
+    sandbox::{
+        Backend,
+        is_root,
+        run_in_namespace,
+    },
     source::SourceError,
 };
 use crate::{
     exec, package::{
-        alias::gather_all_aliases, dep::DepKind, FormError
+        alias::gather_all_aliases, FormError
     }, utils::file::mtime, CONFIG
 };
 
-const MERGED: &str = "/var/lib/to/chroot/merged";
+const CHROOT: &str = "/var/lib/to/chroot";
+pub(super) const MERGED: &str = "/var/lib/to/chroot/merged";
+const UPPER: &str = "/var/lib/to/chroot/upper";
 
 #[rustfmt::skip]
 #[derive(Debug, Error)]
@@ -57,6 +70,9 @@ pub enum BuildError {
     #[error("Failed to execute pre-build hook")]
     PreBuildHook,
 
+    #[error("Failed to set up build namespaces")]
+    Namespace,
+
     #[error("Failed to build")]
     Build,
 
@@ -74,20 +90,33 @@ pub enum BuildError {
 }
 
 impl Package {
-    pub fn build(&self, force: bool) -> Result<(), BuildError> {
+    pub fn build(&self, force: bool, backend: Backend) -> Result<(), BuildError> {
         // If we shouldn't build, and the build isn't forced, exit early
         if !self.should_build() && !force {
             return Err(BuildError::ShouldntBuild)
         }
 
-        clean_overlay()?;
-        setup_overlay()?;
+        let backend = backend.resolve();
+        debug!("Building {self} with the {backend} backend");
+
+        // The overlay is held until the distfile is saved, so another build can't clean it from under
+        // this one
+        let holder = clean_overlay()?;
+        let mounts = setup_overlay(backend)?;
         self.fetch_sources()?;
-        self.populate_overlay()?;
+        self.populate_overlay(backend)?;
         self.pre_build_hook()?;
-        self.chroot_and_run()?;
+        match backend {
+            | Backend::Namespace => self.namespace_and_run()?,
+            | _ => self.chroot_and_run()?,
+        }
+
+        // The overlay is only needed for the build itself. Caching and saving use upper and lower.
+        drop(mounts);
         self.cache_stuff()?;
         self.save_distfile()?;
+        drop(holder);
+        self.log_building();
 
         Ok(())
     }
@@ -116,18 +145,22 @@ impl Package {
     }
 
     // NOTE: Dependencies should be installed after the chroot is entered
-    fn populate_overlay(&self) -> Result<(), BuildError> {
+    //
+    // The namespace backend only mounts the overlay inside its namespaces, so the upper directory
+    // is populated instead
+    fn populate_overlay(&self, backend: Backend) -> Result<(), BuildError> {
         let name = &self.name;
+        let root = if backend == Backend::Namespace { UPPER } else { MERGED };
         info!("Populating overlay for {name}");
         // TODO: Consider dropping `/etc/to/exclude` support
         // - Not sure if I wanna do this because I already wrote and used `il()` :shrug:
         for path in ["B", "D", "S", "etc/to"] {
-            mkdir_p(Path::new(MERGED).join(path)).map_err(|_| BuildError::PopulateOverlay)?
+            mkdir_p(Path::new(root).join(path)).map_err(|_| BuildError::PopulateOverlay)?
         }
 
         exec!(
             r#"
-            cd {MERGED}
+            cd {root}
 
             cp -vf {}                               pkg     # copy pkg file
             cp -vf /usr/share/to/scripts/runner.sh  runner  # copy runner
@@ -149,8 +182,8 @@ impl Package {
             debug!("Copying dependencies to overlay")
         }
 
-        fn copy_to_chroot(path: PathBuf) -> Result<(), BuildError> {
-            let dest = Path::new(MERGED).join(
+        fn copy_to_chroot(root: &str, path: PathBuf) -> Result<(), BuildError> {
+            let dest = Path::new(root).join(
                 path.strip_prefix("/")
                     .map_err(|_| BuildError::PopulateOverlay)?,
             );
@@ -164,7 +197,7 @@ impl Package {
         for source in &self.sources {
             // trace!("Copying over source {source:?}");
             let source_path = source.path(self);
-            let source_dest = Path::new(MERGED).join("S").join(&source.dest);
+            let source_dest = Path::new(root).join("S").join(&source.dest);
 
             if source_path.is_dir() {
                 dircpy::copy_dir(&source_path, &source_dest)
@@ -180,7 +213,7 @@ impl Package {
         for dep in &deps {
             let files = [dep.distfile(), dep.pkgfile(), dep.sfile()];
             for file in files {
-                copy_to_chroot(file)
+                copy_to_chroot(root, file)
                     .map_err(|_| BuildError::PopulateOverlay)?;
             }
 
@@ -202,12 +235,12 @@ impl Package {
             }
 
             for alias_path in alias_paths {
-                let symlink = Path::new(MERGED).join(alias_path.strip_prefix("/").expect("Alias path should be absolute"));
+                let symlink = Path::new(root).join(alias_path.strip_prefix("/").expect("Alias path should be absolute"));
                 debug!("Symlinking '{}' -> '{}'", symlink.display(), &dep.name);
 
                 fs::symlink(&dep.name, &symlink).map_err(|_| BuildError::PopulateOverlay)
                     .permit_if(alias_path.read_link().map(|p| p.to_string_lossy() == dep.name).unwrap_or(false))?;
-                debug_assert_eq!(symlink, Path::new(MERGED).join("var/db/to/pkgs").join(alias_path.file_name().unwrap()));
+                debug_assert_eq!(symlink, Path::new(root).join("var/db/to/pkgs").join(alias_path.file_name().unwrap()));
                 debug_assert_eq!(symlink.read_link().unwrap(), PathBuf::from(&dep.name));
             }
 
@@ -225,7 +258,7 @@ impl Package {
         if deps_str.is_empty() {
             debug!("Not writing deps file since {self:-} has no dependencies");
         } else {
-            let deps_file = format!("{MERGED}/deps");
+            let deps_file = format!("{root}/deps");
             write(deps_file, deps_str).map_err(|_| BuildError::PopulateOverlay)?; // deps file
             debug!("Wrote deps file for {self}");
             trace!("Deps_str: {deps_str}");
@@ -257,10 +290,33 @@ impl Package {
         .map_err(|_| BuildError::Build)
     }
 
+    fn namespace_and_run(&self) -> Result<(), BuildError> {
+        info!("Entering build namespaces for {self}");
+        let cflags = &CONFIG.cflags;
+        let env = [
+            ("MAKEFLAGS", CONFIG.makeflags.clone()),
+            ("RUSTFLAGS", CONFIG.rustflags.clone()),
+            ("CXXFLAGS", cflags.clone()),
+            ("FCFLAGS", cflags.clone()),
+            ("CFLAGS", cflags.clone()),
+            ("FFLAGS", cflags.clone()),
+            ("TO_TEST", CONFIG.tests.to_string()),
+        ];
+
+        match run_in_namespace(Path::new(CHROOT), &env, "/runner") {
+            | Ok(0) => Ok(()),
+            | Ok(code) => {
+                error!("Runner for {self} exited with status {code}");
+                Err(BuildError::Build)
+            },
+            | Err(_) => Err(BuildError::Namespace),
+        }
+    }
+
     fn save_distfile(&self) -> Result<(), BuildError> {
         mkdir_p(self.distdir()).map_err(|_| BuildError::SaveDistfile)?;
         exec!(
-            "cp -vf '/var/lib/to/chroot/upper/pkg.tar.zst' '{}'",
+            "cp -vf '{UPPER}/pkg.tar.zst' '{}'",
             self.distfile().display()
         )
         .map_err(|_| BuildError::SaveDistfile)?;
@@ -272,7 +328,6 @@ impl Package {
     /// # """"Cache"""" reusable stuff
     fn cache_stuff(&self) -> Result<(), BuildError> {
         const LOWER: &str = "/var/lib/to/chroot/lower";
-        const UPPER: &str = "/var/lib/to/chroot/upper";
 
         // Cache make-ca certificates
         if self.dependencies.iter().any(|d| d.name == "make-ca") {
@@ -327,41 +382,64 @@ impl Package {
     }
 }
 
-fn setup_overlay() -> Result<(), BuildError> {
+/// # Extracts the stagefile and, for the chroot backend, mounts the overlay
+///
+/// The returned mounts are unmounted when dropped.
+fn setup_overlay(backend: Backend) -> Result<Option<Mounts>, BuildError> {
     exec!(
         r#"
-        cd        /var/lib/to/chroot
+        cd        {CHROOT}
 
         # extract the stage3 if it's absent
         if [ ! -d lower/dev ]; then 
             tar xpf {stagefile} -C lower
         fi
-
-        mount -vt overlay overlay -o lowerdir=lower,upperdir=upper,workdir=work merged
-        mount -v --bind /dev merged/dev
-        mount -vt devpts devpts -o gid=5,mode=0620 merged/dev/pts
-        mount -vt proc proc merged/proc
-        mount -vt sysfs sysfs merged/sys
-        mount -vt tmpfs tmpfs merged/run
         "#,
         stagefile = CONFIG.stagefile,
     )
-    .map_err(|_| BuildError::SetupOverlay)
+    .map_err(|_| BuildError::SetupOverlay)?;
+
+    // The namespace backend mounts everything inside its namespaces
+    if backend == Backend::Namespace {
+        return Ok(None)
+    }
+
+    let merged = Path::new(MERGED);
+    let overlay = format!("lowerdir={CHROOT}/lower,upperdir={UPPER},workdir={CHROOT}/work");
+    let none = MsFlags::empty();
+
+    let mut mounts = Mounts::default();
+    mounts.mount("overlay", merged, Some("overlay"), none, Some(&overlay))
+        .and_then(|()| mounts.mount("/dev", &merged.join("dev"), None, MsFlags::MS_BIND, None))
+        .and_then(|()| mounts.mount("devpts", &merged.join("dev/pts"), Some("devpts"), none, Some("gid=5,mode=0620")))
+        .and_then(|()| mounts.mount("proc", &merged.join("proc"), Some("proc"), none, None))
+        .and_then(|()| mounts.mount("sysfs", &merged.join("sys"), Some("sysfs"), none, None))
+        .and_then(|()| mounts.mount("tmpfs", &merged.join("run"), Some("tmpfs"), none, None))
+        .map_err(|_| BuildError::SetupOverlay)?;
+
+    Ok(Some(mounts))
 }
 
-fn clean_overlay() -> Result<(), BuildError> {
-    let chroot = Path::new("/var/lib/to/chroot");
+/// # Holds the build overlay and cleans it for a fresh build
+///
+/// The overlay stays held until the returned holder is dropped.
+fn clean_overlay() -> Result<Holder, BuildError> {
+    let chroot = Path::new(CHROOT);
     mkdir_p(chroot).map_err(|_| BuildError::SetupOverlay)?;
 
-    exec!(
-        r#"
-        if mountpoint -q {chroot}/merged; then
-            umount -lR {chroot}/merged
-        fi
-        "#,
-        chroot = chroot.display(),
-    )
-    .map_err(|_| BuildError::CleanOverlay)?;
+    let holder = Holder::acquire().map_err(|_| BuildError::CleanOverlay)?;
+
+    // Mounts are only left behind if a previous build was killed
+    if clean_stale_mounts(&holder).map_err(|_| BuildError::CleanOverlay)? > 0 {
+        return Err(BuildError::CleanOverlay)
+    }
+
+    // Unprivileged builds can leave directories their owner can't write to, such as overlayfs's
+    // work directory, which would otherwise fail removal
+    if !is_root() {
+        exec!("chmod -R u+rwX {chroot}/upper {chroot}/work 2>/dev/null || true", chroot = chroot.display())
+            .map_err(|_| BuildError::CleanOverlay)?;
+    }
 
     rmdir_r(chroot.join("upper")).map_err(|_| BuildError::CleanOverlay)?;
     rmdir_r(chroot.join("work")).map_err(|_| BuildError::CleanOverlay)?;
@@ -370,7 +448,7 @@ fn clean_overlay() -> Result<(), BuildError> {
         mkdir_p(chroot.join(dir)).map_err(|_| BuildError::SetupOverlay)?;
     }
 
-    Ok(())
+    Ok(holder)
 }
 
 /// # Returns the order in which all packages should be built
@@ -408,8 +486,9 @@ pub fn get_build_order(mut all_packages: Vec<Package>) -> Vec<Package> {
             let order_names = order.iter().map(|p| p.name.as_str()).collect::<Vec<_>>();
             let pkg = &all_packages[i];
 
-            // Find build and required dependencies, extracting their names.
-            let mut dependencies = pkg.dependencies.iter().filter(|d| matches!(d.kind, DepKind::Build | DepKind::Required))
+            // Find dependencies needed to build, extracting their names. Runtime dependencies are
+            // skipped since they needn't be built first, and may form cycles.
+            let mut dependencies = pkg.dependencies.iter().filter(|d| d.kind.for_build())
                 .map(|d| d.to_package().unwrap().name);
 
             if dependencies.all(|d| order_names.contains(&d.as_str())) {
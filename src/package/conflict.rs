@@ -0,0 +1,185 @@
+// package/conflict.rs
+//! Conflicts and replacements between packages
+//!
+//! Packages declare `conflicts=()` and `replaces=()` in their pkg files. Either may name packages
+//! or virtual packages. Conflicts are symmetric, so a conflict declared by either package is
+//! enough. A replaced package also conflicts with its replacement, but installing the replacement
+//! removes it instead of refusing to install.
+//!
+//! Separately, files in a package's distfile may already belong to another installed package.
+//! These file conflicts are found before extraction by comparing the distfile's MANIFEST against
+//! the ownership index of the root being installed to.
+
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    io,
+    iter::once,
+    path::Path,
+};
+
+use super::{
+    Package,
+    installed_packages,
+    owners::Owners,
+};
+
+/// # A file in a distfile that already belongs to another installed package
+///
+/// # Fields
+/// * `path`            - The path, relative to the root
+/// * `owner`           - The name of the installed package owning the path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileConflict {
+    pub path:  String,
+    pub owner: String,
+}
+
+/// # Finds the paths owned by another package
+///
+/// Directories are never conflicts. A path is a directory if it has children in the manifest, or
+/// if `is_dir` says so.
+fn find_file_conflicts(
+    paths: &[String],
+    owners: &HashMap<String, String>,
+    is_dir: impl Fn(&str) -> bool,
+) -> Vec<FileConflict> {
+    let dirs = paths
+        .iter()
+        .flat_map(|p| Path::new(p).ancestors().skip(1))
+        .filter_map(Path::to_str)
+        .collect::<HashSet<_>>();
+
+    paths
+        .iter()
+        .filter(|p| !dirs.contains(p.as_str()) && !is_dir(p))
+        .filter_map(|p| {
+            owners.get(p).map(|o| FileConflict {
+                path:  p.clone(),
+                owner: o.clone(),
+            })
+        })
+        .collect()
+}
+
+impl Package {
+    /// # Returns the names a package answers to, being its name and the names it provides
+    fn names(&self) -> impl Iterator<Item = &String> { once(&self.name).chain(&self.provides) }
+
+    /// # Checks whether a package declares a conflict with, or replaces, another
+    fn declares_conflict(&self, other: &Self) -> bool {
+        self.conflicts
+            .iter()
+            .chain(&self.replaces)
+            .any(|c| other.names().any(|n| n == c))
+    }
+
+    /// # Checks whether two packages can't be installed alongside each other
+    pub fn conflicts_with(&self, other: &Self) -> bool {
+        self.name != other.name && (self.declares_conflict(other) || other.declares_conflict(self))
+    }
+
+    /// # Finds the installed packages this package replaces
+    pub fn installed_replaced(&self) -> Vec<Package> {
+        installed_packages()
+            .into_iter()
+            .filter(|p| p.name != self.name && self.replaces.contains(&p.name))
+            .collect()
+    }
+
+    /// # Finds the installed packages that conflict with this package
+    ///
+    /// Packages this package replaces are excluded, since they're removed on install.
+    pub fn installed_conflicts(&self) -> Vec<Package> {
+        installed_packages()
+            .into_iter()
+            .filter(|p| !self.replaces.contains(&p.name) && self.conflicts_with(p))
+            .collect()
+    }
+
+    /// # Finds files in a package's distfile that belong to other installed packages
+    ///
+    /// The distfile's MANIFEST is read without extracting it. Paths owned only by this package or
+    /// by the packages it replaces are ignored.
+    ///
+    /// Ownership is taken from the index when installing to /, and otherwise from the current
+    /// manifests in the root's data directory.
+    ///
+    /// # Errors
+    /// - The distfile's MANIFEST or the ownership index couldn't be read
+    pub fn file_conflicts(&self, root: &Path) -> io::Result<Vec<FileConflict>> {
+        let paths = self.distfile_manifest()?;
+        let ignored = once(self.name.clone())
+            .chain(self.installed_replaced().into_iter().map(|p| p.name))
+            .collect::<HashSet<_>>();
+
+        let owners = Owners::for_root(root)?
+            .paths
+            .into_iter()
+            .filter_map(|(path, owners)| {
+                owners
+                    .into_iter()
+                    .find(|o| !ignored.contains(o))
+                    .map(|o| (path, o))
+            })
+            .collect::<HashMap<_, _>>();
+
+        Ok(find_file_conflicts(&paths, &owners, |p| root.join(p).is_dir()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{
+        FileConflict,
+        find_file_conflicts,
+    };
+    use crate::package::{
+        Package,
+        pkgfile::Pkgfile,
+    };
+
+    fn pkg(contents: &str) -> Package { Pkgfile::parse(contents).unwrap().try_into().unwrap() }
+
+    #[test]
+    fn conflicts() {
+        let sysvinit = pkg("sysvinit@3.14-1\np=(init)\n");
+        let systemd = pkg("systemd@257-1\np=(init libudev)\nconflicts=(init)\n");
+        let eudev = pkg("eudev@3.2.14-1\nconflicts=(libudev)\n");
+        let libjpeg = pkg("libjpeg@9f-1\n");
+        let turbo = pkg("libjpeg-turbo@3.1.0-1\nreplaces=(libjpeg)\n");
+
+        // Conflicts may name virtual packages, and are symmetric
+        assert!(systemd.conflicts_with(&sysvinit));
+        assert!(sysvinit.conflicts_with(&systemd));
+        assert!(eudev.conflicts_with(&systemd));
+
+        // A package providing what it conflicts with doesn't conflict with itself
+        assert!(!systemd.conflicts_with(&systemd));
+
+        // Replacements conflict
+        assert!(libjpeg.conflicts_with(&turbo));
+        assert!(!libjpeg.conflicts_with(&eudev));
+    }
+
+    #[test]
+    fn file_conflicts() {
+        let paths = ["usr", "usr/bin", "usr/bin/vi", "usr/share/man", "etc/vimrc"].map(String::from);
+        let owners = HashMap::from([
+            ("usr/bin".to_string(), "coreutils".to_string()),
+            ("usr/bin/vi".to_string(), "nvi".to_string()),
+            ("usr/share/man".to_string(), "man-pages".to_string()),
+        ]);
+
+        // Shared directories don't conflict, whether inferred from the manifest or the root
+        let conflicts = find_file_conflicts(&paths, &owners, |p| p == "usr/share/man");
+        assert_eq!(conflicts, vec![FileConflict {
+            path:  "usr/bin/vi".to_string(),
+            owner: "nvi".to_string(),
+        }]);
+    }
+}
@@ -14,6 +14,7 @@ use serde::{
     Serialize,
 };
 use tracing::{
+    debug,
     error,
     instrument,
 };
@@ -21,28 +22,163 @@ use tracing::{
 use super::{
     FormError,
     Package,
+    Version,
+    generate::GenerateError,
+    index::Index,
+    provides::{
+        is_virtual,
+        resolve_provider,
+    },
+    version::compare_versions,
 };
 use crate::{
+    CONFIG,
     package::all_package_names,
-    utils::parse::us_array,
 };
 
-pub fn parse_deps(raw: &str) -> Vec<Dep> {
-    us_array(raw).iter().map(|s| Dep::from_string(s)).collect()
+/// # Parses a pkg file's dependencies
+///
+/// # Errors
+/// - `GenerateError::InvalidDependency` if any dependency is malformed
+pub fn parse_deps(raw: &[String]) -> Result<Vec<Dep>, GenerateError> {
+    raw.iter().map(|s| Dep::from_string(s)).collect()
 }
 
+/// # A dependency
+///
+/// Dependencies are written as `[kind,]name[constraint]` in a pkg file, for instance `glibc`,
+/// `b,meson`, `glibc>=2.40`, or `b,meson>=1.5`. The kind prefixes are:
+/// - (none) => `Required`
+/// - `b`    => `Build`
+/// - `r`    => `Runtime`
+/// - `o`    => `Optional`
+/// - `c`    => `Check`
 #[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone, Hash)]
 pub struct Dep {
-    pub name: String,
-    pub kind: DepKind,
+    pub name:       String,
+    pub kind:       DepKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub constraint: Option<Constraint>,
+}
+
+/// # A version constraint on a dependency
+///
+/// Constraints only consider the version, not the release. Since commit hashes can't be ordered,
+/// only `=` and `!=` are checked for them; other operators are assumed to be satisfied.
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone, Hash)]
+pub struct Constraint {
+    pub op:      ConstraintOp,
+    pub version: String,
 }
 
 #[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone, Copy, Hash)]
+pub enum ConstraintOp {
+    Lt,
+    Le,
+    Eq,
+    Ne,
+    Ge,
+    Gt,
+}
+
+impl Constraint {
+    /// # Parses a constraint from the remainder of a dependency string, like `>=2.40`
+    ///
+    /// Returns the reason on failure.
+    fn from_string(str: &str) -> Result<Self, &'static str> {
+        let (op, version) = [
+            (">=", ConstraintOp::Ge),
+            ("<=", ConstraintOp::Le),
+            ("!=", ConstraintOp::Ne),
+            ("==", ConstraintOp::Eq),
+            (">", ConstraintOp::Gt),
+            ("<", ConstraintOp::Lt),
+            ("=", ConstraintOp::Eq),
+        ]
+        .into_iter()
+        .find_map(|(prefix, op)| str.strip_prefix(prefix).map(|v| (op, v)))
+        .ok_or("unknown constraint operator")?;
+
+        let version = version.trim();
+        if version.is_empty() {
+            return Err("missing constraint version")
+        }
+
+        if version.contains(['<', '>', '=', '!']) {
+            return Err("unknown constraint operator")
+        }
+
+        Ok(Self {
+            op,
+            version: version.to_string(),
+        })
+    }
+
+    pub fn satisfied_by(&self, version: &Version) -> bool {
+        let Some(ord) = compare_versions(&version.version, &self.version) else {
+            return match self.op {
+                | ConstraintOp::Ne => true,
+                | ConstraintOp::Eq => false,
+                | _ => {
+                    debug!("Assuming {} satisfies {self} since it can't be ordered", version.version);
+                    true
+                },
+            }
+        };
+
+        match self.op {
+            | ConstraintOp::Lt => ord.is_lt(),
+            | ConstraintOp::Le => ord.is_le(),
+            | ConstraintOp::Eq => ord.is_eq(),
+            | ConstraintOp::Ne => ord.is_ne(),
+            | ConstraintOp::Ge => ord.is_ge(),
+            | ConstraintOp::Gt => ord.is_gt(),
+        }
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self.op {
+            | ConstraintOp::Lt => "<",
+            | ConstraintOp::Le => "<=",
+            | ConstraintOp::Eq => "=",
+            | ConstraintOp::Ne => "!=",
+            | ConstraintOp::Ge => ">=",
+            | ConstraintOp::Gt => ">",
+        };
+        write!(f, "{op}{}", self.version)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone, Copy, Hash, clap::ValueEnum)]
 // NOTE: Doc dependency support has been dropped as I'd rather just include them as make
 // dependencies for the packages for which I want documentation.
 pub enum DepKind {
+    /// Needed to build and to run
     Required,
+    /// Only needed to build
     Build,
+    /// Only needed to run, so it's kept out of the build chroot
+    Runtime,
+    /// Not needed, but suggested after install
+    Optional,
+    /// Only needed to run tests, so it's only in the build chroot if `CONFIG.tests` is set
+    Check,
+}
+
+impl DepKind {
+    /// # Whether dependencies of this kind belong in the build chroot
+    pub fn for_build(self) -> bool {
+        match self {
+            | Self::Required | Self::Build => true,
+            | Self::Check => CONFIG.tests,
+            | Self::Runtime | Self::Optional => false,
+        }
+    }
+
+    /// # Whether dependencies of this kind are installed alongside a package
+    pub fn for_install(self) -> bool { matches!(self, Self::Required | Self::Runtime) }
 }
 
 impl fmt::Display for DepKind {
@@ -50,33 +186,82 @@ impl fmt::Display for DepKind {
         match self {
             | Self::Required => write!(f, "Required"),
             | Self::Build => write!(f, "Build"),
+            | Self::Runtime => write!(f, "Runtime"),
+            | Self::Optional => write!(f, "Optional"),
+            | Self::Check => write!(f, "Check"),
         }
     }
 }
 
 impl Dep {
-    pub fn from_string(str: &str) -> Self {
-        if let Some((kind, str)) = str.split_once(',') {
+    /// # Parses a dependency from a pkg file, like `b,meson>=1.5`
+    ///
+    /// # Errors
+    /// - `GenerateError::InvalidDependency` for an unknown kind or constraint operator, or a missing
+    ///   name or constraint version
+    pub fn from_string(str: &str) -> Result<Self, GenerateError> {
+        let invalid = |reason| GenerateError::InvalidDependency {
+            dep: str.to_string(),
+            reason,
+        };
+
+        let (kind, rest) = if let Some((kind, rest)) = str.split_once(',') {
             let kind = match kind {
                 | "b" => DepKind::Build,
-                | _ => panic!("Unknown dep kind: {kind}"),
+                | "r" => DepKind::Runtime,
+                | "o" => DepKind::Optional,
+                | "c" => DepKind::Check,
+                | _ => return Err(invalid("unknown dependency kind")),
             };
 
-            Self { name: str.to_string(), kind }
+            (kind, rest)
         } else {
-            Self {
-                name: str.to_string(),
-                kind: DepKind::Required,
-            }
+            (DepKind::Required, str)
+        };
+
+        let (name, constraint) = match rest.find(['<', '>', '=', '!']) {
+            | Some(i) => (&rest[..i], Some(Constraint::from_string(&rest[i..]).map_err(invalid)?)),
+            | None => (rest, None),
+        };
+
+        if name.is_empty() {
+            return Err(invalid("missing name"))
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            kind,
+            constraint,
+        })
+    }
+
+    /// # Checks whether a package satisfies this dependency's version constraint
+    ///
+    /// The package's repository version is checked. This is used during dependency resolution to
+    /// ensure the repository can satisfy a constraint.
+    ///
+    /// # Errors
+    /// - `FormError::Unsatisfiable` if the package's version fails the constraint
+    pub fn check(&self, pkg: &Package) -> Result<(), FormError> {
+        match &self.constraint {
+            | Some(c) if !c.satisfied_by(&pkg.version) => {
+                error!("Dependency {self} is not satisfied by {pkg:-}");
+                Err(FormError::Unsatisfiable(format!("{self} (found {pkg:-})")))
+            },
+            | _ => Ok(()),
         }
     }
 
     /// # Convert a dependency to a package
     ///
     /// This function does not sacrifice dependency data. `DepKind` is added as a field to
-    /// `Package`.
+    /// `Package`. Dependencies on virtual packages are resolved to a provider.
     pub fn to_package(&self) -> Result<Package, FormError> {
-        let mut pkg = Package::from_s_file(&self.name)?;
+        let mut pkg = if is_virtual(&self.name) {
+            resolve_provider(&self.name)?
+        } else {
+            Package::from_s_file(&self.name)?
+        };
         pkg.depkind = Some(self.kind);
         Ok(pkg)
     }
@@ -93,8 +278,9 @@ impl Package {
     #[cfg(test)]
     pub fn to_dep(&self) -> Result<Dep, FormError> {
         Ok(Dep {
-            name: self.name.clone(),
-            kind: self
+            name:       self.name.clone(),
+            constraint: None,
+            kind:       self
                 .depkind
                 .ok_or(FormError::MissingMetadata("depkind".to_owned()))?,
         })
@@ -102,13 +288,18 @@ impl Package {
 
     /// # Find shallow dependants for a package
     ///
-    /// This function gathers all packages, and checks to see if their shallow dependencies contain
-    /// `self.name`.
+    /// This function uses the reverse dependency edges in the index if it's available. Otherwise,
+    /// it gathers all packages, and checks to see if their shallow dependencies contain
+    /// `self.name`, or any name `self` provides.
     ///
     /// # Errors
     /// - Will fail if `self` could not be converted to a `Dep`
     /// - Will fail if any package could not be formed
     pub fn dependants(&self) -> Result<Vec<Package>, FormError> {
+        if let Some(index) = Index::cached() {
+            return Ok(index.dependants_of(self).into_iter().cloned().collect())
+        }
+
         let all_packages = all_package_names()
             .iter()
             .map(|p| Package::from_s_file(p).inspect_err(|e| error!("Failed to form {p}: {e}")))
@@ -116,7 +307,7 @@ impl Package {
 
         let mut dependants = Vec::new();
         for package in all_packages {
-            if package.dependencies.iter().any(|d| d.name == self.name) {
+            if package.dependencies.iter().any(|d| d.name == self.name || self.provides.contains(&d.name)) {
                 dependants.push(package)
             }
         }
@@ -124,7 +315,137 @@ impl Package {
         Ok(dependants)
     }
 
-    fn build_dep_graph(
+    /// # Finds the kinds of dependencies a package has on this package
+    ///
+    /// Dependencies on virtual packages this package provides are included.
+    fn dependency_kinds_of(&self, dependant: &Package) -> Vec<DepKind> {
+        dependant
+            .dependencies
+            .iter()
+            .filter(|d| d.name == self.name || self.provides.contains(&d.name))
+            .map(|d| d.kind)
+            .collect()
+    }
+
+    /// # Builds a reversed dependency graph, where edges point from a package to its dependants
+    ///
+    /// Only edges whose kind passes `filter` are followed, and with `installed_only`, only
+    /// installed dependants are. Each node's first discovered parent is recorded in `parents`,
+    /// which gives the shortest chain back to the root.
+    ///
+    /// Shallow dependants are found with `dependants_of`, and installation is checked with
+    /// `is_installed`.
+    fn build_rev_dep_graph(
+        &self,
+        graph: &mut DiGraph<Package, DepKind>,
+        parents: &mut HashMap<NodeIndex, NodeIndex>,
+        filter: impl Fn(DepKind) -> bool + Copy,
+        installed_only: bool,
+        dependants_of: impl Fn(&Package) -> Result<Vec<Package>, FormError>,
+        is_installed: impl Fn(&Package) -> bool,
+    ) -> Result<NodeIndex, FormError> {
+        let root = graph.add_node(self.clone());
+        let mut index_map = HashMap::from([(self.name.clone(), root)]);
+        let mut queue = std::collections::VecDeque::from([root]);
+
+        while let Some(idx) = queue.pop_front() {
+            let pkg = graph[idx].clone();
+            for dependant in dependants_of(&pkg)? {
+                if installed_only && !is_installed(&dependant) {
+                    continue
+                }
+
+                let Some(kind) = pkg.dependency_kinds_of(&dependant).into_iter().find(|k| filter(*k)) else {
+                    continue
+                };
+
+                let dep_idx = match index_map.get(&dependant.name) {
+                    | Some(&i) => i,
+                    | None => {
+                        let i = graph.add_node(dependant.clone());
+                        index_map.insert(dependant.name.clone(), i);
+                        parents.insert(i, idx);
+                        queue.push_back(i);
+                        i
+                    },
+                };
+
+                graph.add_edge(idx, dep_idx, kind);
+            }
+        }
+
+        Ok(root)
+    }
+
+    /// # Finds deep dependants for a package
+    ///
+    /// Each dependant is returned with the chain of packages leading from it to `self`, where the
+    /// first element is the dependant's direct dependency and the last is `self`. Dependants are
+    /// ordered by distance from `self`.
+    ///
+    /// # Arguments
+    /// * `filter`          - Which dependency kinds to follow
+    /// * `installed_only`  - Whether to only follow installed dependants
+    ///
+    /// # Errors
+    /// - Will fail if any package could not be formed
+    pub fn deep_dependants(
+        &self,
+        filter: impl Fn(DepKind) -> bool + Copy,
+        installed_only: bool,
+    ) -> Result<Vec<(Package, Vec<String>)>, FormError> {
+        self.deep_dependants_with(
+            filter,
+            installed_only,
+            Package::dependants,
+            Package::is_installed,
+        )
+    }
+
+    /// # Finds deep dependants for a package, with `build_rev_dep_graph()`'s lookups
+    fn deep_dependants_with(
+        &self,
+        filter: impl Fn(DepKind) -> bool + Copy,
+        installed_only: bool,
+        dependants_of: impl Fn(&Package) -> Result<Vec<Package>, FormError>,
+        is_installed: impl Fn(&Package) -> bool,
+    ) -> Result<Vec<(Package, Vec<String>)>, FormError> {
+        let mut graph = DiGraph::<Package, DepKind>::new();
+        let mut parents = HashMap::new();
+        let root = self.build_rev_dep_graph(
+            &mut graph,
+            &mut parents,
+            filter,
+            installed_only,
+            dependants_of,
+            is_installed,
+        )?;
+
+        let mut order = petgraph::visit::Bfs::new(&graph, root);
+        let mut dependants = Vec::new();
+        while let Some(idx) = order.next(&graph) {
+            if idx == root {
+                continue
+            }
+
+            let mut chain = Vec::new();
+            let mut cur = idx;
+            while let Some(&parent) = parents.get(&cur) {
+                chain.push(graph[parent].name.clone());
+                cur = parent;
+            }
+
+            let mut pkg = graph[idx].clone();
+            pkg.depkind = graph
+                .find_edge(parents[&idx], idx)
+                .map(|e| graph[e]);
+            dependants.push((pkg, chain));
+        }
+
+        Ok(dependants)
+    }
+
+    pub(super) fn build_dep_graph(
         &self,
         graph: &mut DiGraph<Package, ()>,
         index_map: &mut HashMap<String, NodeIndex>,
@@ -143,6 +464,7 @@ impl Package {
             }
 
             let dep_pkg = dep.to_package()?;
+            dep.check(&dep_pkg)?;
             let dep_idx = dep_pkg.build_dep_graph(graph, index_map, filter)?;
             graph.add_edge(dep_idx, idx, ());
         }
@@ -172,9 +494,10 @@ impl Package {
 
     /// # Collects all dependencies that should be in the build chroot
     ///
-    /// The idea is to first collect deep required dependencies, then shallow build dependencies,
-    /// then the deep required dependencies for those shallow build dependencies. Finally, a
-    /// topological sort is performed to order them correctly.
+    /// The idea is to first collect deep required dependencies, then shallow build (and check, if
+    /// tests are enabled) dependencies, then the deep required dependencies for those. Finally, a
+    /// topological sort is performed to order them correctly. Runtime and optional dependencies
+    /// are never pulled in.
     ///
     /// # Errors
     /// - Will fail if a dependency could not be converted to a package
@@ -192,12 +515,13 @@ impl Package {
             all.entry(pkg.name.clone()).or_insert_with(|| pkg.clone());
         }
 
-        // 2. Collect shallow build dependencies
+        // 2. Collect shallow build and check dependencies
         let build_deps = self
             .dependencies
             .iter()
-            .filter(|d| d.kind == DepKind::Build && !deps.iter().any(|dep| dep.name == d.name))
-            .map(|d| d.to_package())
+            .filter(|d| d.kind != DepKind::Required && d.kind.for_build())
+            .filter(|d| !deps.iter().any(|dep| dep.name == d.name))
+            .map(|d| d.to_package().and_then(|p| d.check(&p).map(|_| p)))
             .collect::<Result<Vec<_>, _>>()?;
 
         for pkg in &build_deps {
@@ -223,11 +547,18 @@ impl Package {
         for pkg in all.values() {
             let from = indices[&pkg.name];
             for dep in &pkg.dependencies {
-                if !matches!(dep.kind, DepKind::Required | DepKind::Build) {
+                if !dep.kind.for_build() {
                     continue
                 }
 
-                if let Some(to) = indices.get(&dep.name) {
+                // Dependencies on virtual packages are ordered after the chosen provider
+                let name = if is_virtual(&dep.name) {
+                    resolve_provider(&dep.name)?.name
+                } else {
+                    dep.name.clone()
+                };
+
+                if let Some(to) = indices.get(&name) {
                     graph.add_edge(*to, from, ());
                 }
             }
@@ -253,21 +584,33 @@ impl Package {
 
     /// # Collects all dependencies that should be installed
     ///
-    /// This function used to only install *runtime* dependencies when not in the chroot
-    /// environment. It now only installs required dependencies, regardless of context.
+    /// Required and runtime dependencies are installed, deeply. Optional dependencies are only
+    /// suggested, and build and check dependencies are left to the build chroot.
     // TODO: Consider refactoring this function away
-    pub fn collect_install_deps(&self) -> Vec<Package> {
-        self.resolve_deps(|k| matches!(k, DepKind::Required))
+    pub fn collect_install_deps(&self) -> Vec<Package> { self.resolve_deps(DepKind::for_install) }
+
+    /// # Lists the optional dependencies of a package that aren't installed
+    pub fn missing_optional_deps(&self) -> Vec<&Dep> {
+        self.dependencies
+            .iter()
+            .filter(|d| d.kind == DepKind::Optional)
+            .filter(|d| !d.to_package().is_ok_and(|p| p.is_installed()))
+            .collect()
     }
 }
 
 impl fmt::Display for Dep {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(c) = &self.constraint {
+            write!(f, "{c}")?;
+        }
+
         if f.sign_plus() {
-            write!(f, "{} ({})", self.name, self.kind)
-        } else {
-            write!(f, "{}", self.name)
+            write!(f, " ({})", self.kind)?;
         }
+
+        Ok(())
     }
 }
 
@@ -434,6 +777,119 @@ mod test {
         assert!(deps.iter().any(|d| d.name == "util-macros"));
     }
 
+    #[test]
+    fn constraints() {
+        use super::{
+            ConstraintOp,
+            Dep,
+            GenerateError,
+        };
+
+        let dep = Dep::from_string("b,meson>=1.5").unwrap();
+        assert_eq!(dep.name, "meson");
+        assert_eq!(dep.kind, DepKind::Build);
+        let c = dep.constraint.as_ref().unwrap();
+        assert_eq!(c.op, ConstraintOp::Ge);
+        assert!(c.satisfied_by(&"1.5.2-1".parse().unwrap()));
+        assert!(!c.satisfied_by(&"1.4.0-3".parse().unwrap()));
+        assert_eq!(dep.to_string(), "meson>=1.5");
+
+        let dep = Dep::from_string("openssl<4").unwrap();
+        assert_eq!(dep.kind, DepKind::Required);
+        assert!(!dep.constraint.unwrap().satisfied_by(&"4.0.1-1".parse().unwrap()));
+
+        assert!(Dep::from_string("glibc").unwrap().constraint.is_none());
+
+        for invalid in ["foo>=", "foo= ", "foo=>1", "foo!1", ">=1", "x,foo"] {
+            assert!(
+                matches!(Dep::from_string(invalid), Err(GenerateError::InvalidDependency { .. })),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn kinds() {
+        use super::Dep;
+
+        assert_eq!(Dep::from_string("r,ca-certs").unwrap().kind, DepKind::Runtime);
+        assert_eq!(Dep::from_string("o,bash-completion").unwrap().kind, DepKind::Optional);
+        assert_eq!(Dep::from_string("c,python>=3.12").unwrap().kind, DepKind::Check);
+
+        assert!(DepKind::Runtime.for_install() && !DepKind::Runtime.for_build());
+        assert!(!DepKind::Optional.for_install() && !DepKind::Optional.for_build());
+        assert!(!DepKind::Check.for_install());
+        assert!(DepKind::Build.for_build() && !DepKind::Build.for_install());
+    }
+
+    /// `curl` needs `zlib`, `git` needs `curl` and builds with `zlib`, and `docs` optionally uses
+    /// `curl`. Everything but `git` is installed.
+    fn graph() -> Vec<Package> {
+        vec![
+            Package::stub("zlib", &[]),
+            Package::stub("curl", &[("zlib", DepKind::Required)]),
+            Package::stub("git", &[
+                ("curl", DepKind::Required),
+                ("zlib", DepKind::Build),
+            ]),
+            Package::stub("docs", &[("curl", DepKind::Optional)]),
+        ]
+    }
+
+    fn deep_dependants(
+        name: &str,
+        filter: impl Fn(DepKind) -> bool + Copy,
+        installed_only: bool,
+    ) -> Vec<(String, Option<DepKind>, String)> {
+        let pkgs = graph();
+        let pkg = pkgs.iter().find(|p| p.name == name).unwrap();
+        let dependants_of = |p: &Package| {
+            Ok(pkgs
+                .iter()
+                .filter(|d| d.dependencies.iter().any(|dep| dep.name == p.name))
+                .cloned()
+                .collect())
+        };
+
+        pkg.deep_dependants_with(filter, installed_only, dependants_of, |p| p.name != "git")
+            .unwrap()
+            .into_iter()
+            .map(|(p, chain)| (p.name, p.depkind, chain.join(" ")))
+            .collect()
+    }
+
+    #[test]
+    fn deep_dependants_bfs() {
+        let deps = deep_dependants("zlib", |_| true, false);
+
+        // Dependants are ordered by distance, and git is reached directly through its build edge
+        assert_eq!(deps.len(), 3);
+        assert!(deps[..2].contains(&("curl".into(), Some(DepKind::Required), "zlib".into())));
+        assert!(deps[..2].contains(&("git".into(), Some(DepKind::Build), "zlib".into())));
+        assert_eq!(
+            deps[2],
+            ("docs".into(), Some(DepKind::Optional), "curl zlib".into())
+        );
+    }
+
+    #[test]
+    fn deep_dependants_filtered() {
+        // Without the build edge, git is only reached through curl
+        let deps = deep_dependants("zlib", DepKind::for_install, false);
+        assert_eq!(deps, [
+            ("curl".into(), Some(DepKind::Required), "zlib".into()),
+            ("git".into(), Some(DepKind::Required), "curl zlib".into()),
+        ]);
+
+        // Uninstalled dependants aren't followed
+        let deps = deep_dependants("zlib", DepKind::for_install, true);
+        assert_eq!(deps, [(
+            "curl".into(),
+            Some(DepKind::Required),
+            "zlib".into()
+        )]);
+    }
+
     // TODO: Maybe just rewrite this test completely
     //
     // /// Since make-ca is both a runtime and a required dependency, some weird shit used to happen.
@@ -470,7 +926,7 @@ mod test {
     //
     //     let deps = all_deps
     //         .into_iter()
-    //         .filter(|d| d.depkind.expect("Dep should have a kind") != DepKind::Recommended)
+    //         .filter(|d| d.depkind.expect("Dep should have a kind") != DepKind::Optional)
     //         .collect::<Vec<_>>();
     //
     //     assert!(deps.iter().any(|d| d.name == "make-ca"));
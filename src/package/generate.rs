@@ -7,6 +7,7 @@ use std::{
 
 use thiserror::Error;
 use tracing::{
+    error,
     info,
     instrument,
 };
@@ -15,6 +16,30 @@ use crate::package::Package;
 
 #[derive(Error, Debug)]
 pub enum GenerateError {
+    #[error("Failed to read pkgfile: {0}")]
+    ReadPkgfile(io::Error),
+
+    #[error("Missing or malformed header (expected name@version-release)")]
+    MissingHeader,
+
+    #[error("Invalid release '{release}' on line {line} (expected a u8)")]
+    InvalidRelease { line: usize, release: String },
+
+    #[error("Syntax error on line {line}: {reason}")]
+    Syntax { line: usize, reason: String },
+
+    #[error("Dynamic expression on line {line}: {expr}")]
+    Dynamic { line: usize, expr: String },
+
+    #[error("Invalid dependency '{dep}': {reason}")]
+    InvalidDependency { dep: String, reason: &'static str },
+
+    #[error("Failed to evaluate pkgfile with gen.sh: {0}")]
+    Fallback(io::Error),
+
+    #[error("Expected 16 lines from gen.sh, got {0}")]
+    FallbackOutput(usize),
+
     #[error("Failed to serialize package")]
     Serialization(#[from] serde_json::Error),
 
@@ -25,8 +50,7 @@ pub enum GenerateError {
 impl Package {
     #[instrument(level = "debug")]
     pub fn generate(name: &str) -> Result<(), GenerateError> {
-        // TODO: Consider making new() return an error as well
-        let pkg = super::Package::new(name);
+        let pkg = super::Package::new(name).inspect_err(|e| error!("Failed to generate {name}: {e}"))?;
         let s = serde_json::to_string_pretty(&pkg)?;
         write(format!("/var/db/to/pkgs/{name}/s"), s)?;
 
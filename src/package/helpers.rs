@@ -2,12 +2,14 @@
 
 use std::{
     fs::read_to_string,
+    io,
     path::PathBuf,
 };
 
-use tracing::{error, instrument};
+use tracing::{debug, error, instrument};
 
 use super::{Package, Version};
+use crate::exec;
 
 impl Package {
     #[instrument(level = "debug")]
@@ -31,6 +33,35 @@ impl Package {
     // PERF: Strong memoization candidate
     pub fn pkgfile(&self) -> PathBuf { self.pkgdir().join("pkg") }
 
+    /// # Runs a hook function from the pkgfile, if it's defined
+    ///
+    /// `vars` are exported before the hook runs, as (name, value) pairs.
+    ///
+    /// # Errors
+    /// - The pkgfile couldn't be sourced
+    /// - The hook failed
+    pub fn run_hook(&self, hook: &str, vars: &[(&str, String)]) -> io::Result<()> {
+        debug!("Running {hook}() for {self:-}");
+        let vars = vars
+            .iter()
+            .map(|(k, v)| format!("export {k}={v:?}\n"))
+            .collect::<String>();
+
+        exec!(
+            r#"
+
+        set -euo pipefail
+        tource {pkgfile:?}
+        {vars}
+        if is_function {hook}; then
+            {hook}
+        fi
+
+        "#,
+            pkgfile = self.pkgfile()
+        )
+    }
+
     // PERF: Strong memoization candidate
     pub fn sfile(&self) -> PathBuf { self.pkgdir().join("s") }
 
@@ -0,0 +1,206 @@
+// package/index.rs
+//! Compiled repository index
+//!
+//! Forming every package from its s file is slow for commands that operate on the whole
+//! repository. The index serializes every package, alias, and reverse dependency edge into
+//! `/var/db/to/pkgs/.index`, which is rebuilt by `to generate` and `to sync`.
+//!
+//! The index is ignored if it's stale, meaning any s file is newer than it, or the packages or
+//! aliases in the repository have changed.
+
+use std::{
+    collections::{
+        BTreeMap,
+        BTreeSet,
+    },
+    fs::{
+        self,
+        read_to_string,
+        rename,
+    },
+    io,
+    path::Path,
+    sync::LazyLock,
+    time::SystemTime,
+};
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use thiserror::Error;
+use tracing::{
+    debug,
+    info,
+    instrument,
+    warn,
+};
+
+use super::{
+    FormError,
+    Package,
+    all_package_names,
+    alias::gather_all_aliases,
+    dep::Dep,
+};
+use crate::utils::file::mtime;
+
+pub const INDEX: &str = "/var/db/to/pkgs/.index";
+
+static CACHED: LazyLock<Option<Index>> = LazyLock::new(Index::load);
+
+#[derive(Debug, Error)]
+pub enum IndexError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Failed to form package: {0}")]
+    FormError(#[from] FormError),
+
+    #[error("Failed to (de)serialize index: {0}")]
+    Serde(#[from] serde_json::Error),
+}
+
+/// # The compiled repository index
+///
+/// # Fields
+/// * `packages`        - Every package in the repository, keyed by name.
+/// * `aliases`         - Every alias, mapped to the name of the package it points to.
+/// * `dependants`      - Reverse dependency edges. Each package is mapped to the packages that
+///   depend on it, where the kind is the kind of the dependency. Dependencies on aliases are
+///   resolved to the original package, while dependencies on virtual packages are kept as is.
+/// * `providers`       - Every virtual package name, mapped to the packages that provide it.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Index {
+    pub packages:   BTreeMap<String, Package>,
+    pub aliases:    BTreeMap<String, String>,
+    pub dependants: BTreeMap<String, Vec<Dep>>,
+    #[serde(default)]
+    pub providers:  BTreeMap<String, Vec<String>>,
+}
+
+impl Index {
+    /// # Builds the index from every s file and alias in the repository
+    #[instrument]
+    pub fn build() -> Result<Self, IndexError> {
+        let mut index = Self::default();
+
+        for name in all_package_names() {
+            let pkg = Package::read_s_file(&name)?;
+            index.packages.insert(pkg.name.clone(), pkg);
+        }
+
+        for alias in gather_all_aliases() {
+            let target = fs::read_link(Path::new("/var/db/to/pkgs").join(&alias.name))?;
+            let original = target
+                .file_name()
+                .map(|f| f.to_string_lossy().to_string())
+                .ok_or(io::Error::from(io::ErrorKind::InvalidFilename))?;
+            index.aliases.insert(alias.name, original);
+        }
+
+        let edges = index
+            .packages
+            .values()
+            .flat_map(|pkg| {
+                pkg.dependencies.iter().map(|dep| {
+                    let target = index.resolve(&dep.name).map_or(&dep.name, |p| &p.name);
+                    (target.clone(), Dep {
+                        name:       pkg.name.clone(),
+                        kind:       dep.kind,
+                        constraint: dep.constraint.clone(),
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for (target, edge) in edges {
+            index.dependants.entry(target).or_default().push(edge);
+        }
+
+        for pkg in index.packages.values() {
+            for name in &pkg.provides {
+                index.providers.entry(name.clone()).or_default().push(pkg.name.clone());
+            }
+        }
+
+        Ok(index)
+    }
+
+    /// # Writes the index to disk
+    ///
+    /// The index is written to a part file first, then moved into place.
+    pub fn write(&self) -> Result<(), IndexError> {
+        let part = format!("{INDEX}.part");
+        fs::write(&part, serde_json::to_string(self)?)?;
+        rename(part, INDEX)?;
+
+        info!("Wrote package index with {} packages", self.packages.len());
+        Ok(())
+    }
+
+    /// # Loads the index from disk, returning `None` if it's missing, invalid, or stale
+    fn load() -> Option<Self> {
+        let Some(index_mtime) = mtime(INDEX) else {
+            debug!("No package index at {INDEX}");
+            return None
+        };
+
+        let contents = read_to_string(INDEX)
+            .inspect_err(|e| warn!("Failed to read package index: {e}"))
+            .ok()?;
+
+        let index: Self = serde_json::from_str(&contents)
+            .inspect_err(|e| warn!("Failed to deserialize package index: {e}"))
+            .ok()?;
+
+        if index.is_stale(index_mtime) {
+            debug!("Ignoring stale package index");
+            return None
+        }
+
+        Some(index)
+    }
+
+    /// # Returns the index loaded for this invocation, if it exists and is fresh
+    pub fn cached() -> Option<&'static Self> { CACHED.as_ref() }
+
+    fn is_stale(&self, index_mtime: SystemTime) -> bool {
+        let names = all_package_names();
+        if names.len() != self.packages.len() {
+            return true
+        }
+
+        let aliases = gather_all_aliases()
+            .into_iter()
+            .map(|a| a.name)
+            .collect::<BTreeSet<_>>();
+        if !aliases.iter().eq(self.aliases.keys()) {
+            return true
+        }
+
+        names.iter().any(|n| {
+            !self.packages.contains_key(n)
+                || mtime(format!("/var/db/to/pkgs/{n}/s")).is_none_or(|m| m > index_mtime)
+        })
+    }
+
+    /// # Finds a package by its name or one of its aliases
+    pub fn resolve(&self, name: &str) -> Option<&Package> {
+        self.packages
+            .get(name)
+            .or_else(|| self.aliases.get(name).and_then(|n| self.packages.get(n)))
+    }
+
+    /// # Finds the packages that depend on a package or anything it provides, each listed once
+    pub fn dependants_of(&self, pkg: &Package) -> Vec<&Package> {
+        let mut seen = BTreeSet::new();
+        std::iter::once(&pkg.name)
+            .chain(&pkg.provides)
+            .filter_map(|n| self.dependants.get(n))
+            .flatten()
+            .filter(|d| seen.insert(&d.name))
+            .filter_map(|d| self.packages.get(&d.name))
+            .collect()
+    }
+}
@@ -1,12 +1,14 @@
 // package/install.rs
 
 use std::{
+    cmp::Ordering,
     collections::HashSet,
     fs,
     path::Path,
 };
 
 use fshelpers::mkdir_p;
+use glob::Pattern;
 use once_cell::sync::Lazy;
 use permitit::Permit;
 use thiserror::Error;
@@ -14,13 +16,18 @@ use tracing::{
     debug,
     error,
     info,
-    instrument,
     warn,
 };
 
 use super::{
     FormError,
     Package,
+    Version,
+    reason::Reason,
+    remove::{
+        RemoveError,
+        find_dead_files,
+    },
 };
 use crate::{
     exec,
@@ -50,6 +57,18 @@ pub enum InstallError {
     #[error("Failed to install dependencies")]
     Dependencies(Box<InstallError>),
 
+    #[error("Unsatisfied dependency: {0}")]
+    UnsatisfiedDependency(String),
+
+    #[error("Conflicts with installed package(s): {0}")]
+    Conflict(String),
+
+    #[error("{0} file(s) belong to other installed packages")]
+    FileConflict(usize),
+
+    #[error("Failed to remove replaced package: {0}")]
+    Replace(#[from] RemoveError),
+
     #[error("Failed to execute install command")]
     Execution,
 
@@ -58,8 +77,9 @@ pub enum InstallError {
 }
 
 impl Package {
-    /// Core logic of `install()`, split into a separate function to take the visited hashmap
-    /// generated by the upper `install()`. This is used to avoid pesky infinite recursion.
+    /// Core logic of installing a package, taking a visited hashmap shared by the caller. This is
+    /// used to avoid pesky infinite recursion, and lets callers skip packages they install
+    /// themselves.
     pub fn install_inner(
         &self,
         force: bool,
@@ -67,6 +87,7 @@ impl Package {
         visited: &mut HashSet<String>, // cheaper to clone than `Package`
         suppress: bool,
         root: Option<&str>, // the root to which packages are installed, defaulting to /
+        overwrite: &[Pattern], // paths owned by other packages that may be overwritten
     ) -> Result<(), InstallError> {
         // Make `full_force` imply `force`
         let force = full_force || force;
@@ -78,7 +99,6 @@ impl Package {
 
         let version = &self.version;
         let dist = self.distfile();
-        let dist_str = dist.display();
 
         let installed_version = self.installed_version();
         let updating = installed_version
@@ -97,18 +117,85 @@ impl Package {
             return Err(InstallError::MissingDistfile)
         }
 
-        // Only install required dependencies
+        let conflicts = self.installed_conflicts();
+        if !conflicts.is_empty() {
+            let conflicts = conflicts.iter().map(|p| p.name.as_str()).collect::<Vec<_>>().join(", ");
+            if !force {
+                error!("{self:-} conflicts with installed package(s): {conflicts}");
+                error!("To install anyway, pass --force");
+                return Err(InstallError::Conflict(conflicts))
+            }
+
+            warn!("Forcibly installing {self:-} despite conflicts with: {conflicts}");
+        }
+
+        // Replaced packages are checked before anything is extracted, so one that can't be removed
+        // isn't left installed alongside its replacement
+        let replaced = self.installed_replaced();
+        for pkg in &replaced {
+            pkg.check_removal(false, false, Some(self))
+                .inspect_err(|e| error!("{self:-} replaces {pkg:-}, which can't be removed: {e}"))?;
+        }
+
+        let file_conflicts = self
+            .file_conflicts(Path::new(root.unwrap_or("/")))?
+            .into_iter()
+            .filter(|c| {
+                !overwrite
+                    .iter()
+                    .any(|p| p.matches(&c.path) || p.matches(&format!("/{}", c.path)))
+            })
+            .collect::<Vec<_>>();
+        if !file_conflicts.is_empty() {
+            for c in &file_conflicts {
+                error!("/{} from {self:-} is owned by {}", c.path, c.owner);
+            }
+            error!("To overwrite them anyway, pass --overwrite <glob>");
+            return Err(InstallError::FileConflict(file_conflicts.len()))
+        }
+
+        if let Some(iv) = installed_version.as_ref().filter(|_| updating) {
+            match iv.compare(version) {
+                | Some(Ordering::Less) => info!("Upgrading {} from {}", self.name, iv.srversion()),
+                | Some(Ordering::Greater) => warn!("Downgrading {} from {}", self.name, iv.srversion()),
+                | _ => info!("Updating {} from {}", self.name, iv.srversion()),
+            }
+        }
+
+        // Only install required and runtime dependencies
         let deps = self.collect_install_deps();
         for dep in deps {
-            dep.install_inner(full_force, full_force, visited, suppress, root)
+            dep.install_inner(full_force, full_force, visited, suppress, root, overwrite)
                 .permit(|e| matches!(e, InstallError::AlreadyInstalled))
                 .map_err(|e| InstallError::Dependencies(Box::new(e)))?
         }
 
+        // Installing dependencies upgrades (or downgrades) any that differ from the repository, so
+        // an installed dependency that still fails its constraint can't be satisfied
+        for dep in self.dependencies.iter().filter(|d| d.kind.for_install() && d.constraint.is_some()) {
+            let Some(iv) = dep.to_package()?.installed_version() else {
+                continue
+            };
+
+            if dep.constraint.as_ref().is_some_and(|c| !c.satisfied_by(&iv)) {
+                error!("Installed {}@{} does not satisfy {dep} for {self:-}", dep.name, iv.srversion());
+                return Err(InstallError::UnsatisfiedDependency(dep.to_string()))
+            }
+        }
+
         let data = &self.datadir();
         let iv = data.join("IV");
         let manifest = data.join(format!("MANIFEST@{}", version.srversion()));
         let pkgfile = &self.pkgfile();
+        let root_path = Path::new(root.unwrap_or("/"));
+
+        // Update hooks receive the old and new versions as $ov and $nv. Like the install hooks,
+        // they're skipped when installing to another root.
+        let update_hooks = updating && root_path == Path::new("/");
+        let update_vars = [
+            ("ov", installed_version.as_ref().map(Version::rversion).unwrap_or_default()),
+            ("nv", version.rversion()),
+        ];
 
         mkdir_p(data)?;
         exec!(
@@ -124,11 +211,56 @@ impl Package {
         fi
 
         mkdir -p "{root}"
-        tar xf '{dist_str}' -C "{root}" \
-            --keep-directory-symlink    \
-            --numeric-owner             \
-            --no-overwrite-dir
-        mv -f /MANIFEST {manifest:?}
+
+        "#,
+        root = root.unwrap_or("/")
+        )
+        .map_err(|_| InstallError::Execution)?;
+
+        if update_hooks {
+            self.run_hook("preu", &update_vars).map_err(|e| {
+                error!("preu() failed for {self:-}: {e}");
+                InstallError::Execution
+            })?;
+        }
+
+        // The distfile is extracted into a staging directory first, so a failed extraction never
+        // touches the root
+        let mut stage = self.stage(root_path).map_err(|e| {
+            error!("Failed to stage {self:-}: {e}");
+            InstallError::Execution
+        })?;
+
+        // Modified protected files are moved aside so extraction doesn't clobber them
+        let mut protected = match self.hold_protected(root_path) {
+            | Ok(p) => p,
+            | Err(e) => {
+                stage.rollback();
+                return Err(e.into())
+            },
+        };
+
+        if let Err(e) = stage.apply() {
+            error!("Failed to install files for {self:-}: {e}");
+            stage.rollback();
+            protected.restore();
+            return Err(InstallError::Execution)
+        }
+
+        // Until the stage is committed, any failure rolls back the files and data files. Held
+        // files are excluded, since settling or restoring puts the user's versions back.
+        stage.keep(protected.held());
+        let result = protected
+            .settle(self)
+            .and_then(|()| fs::copy(stage.manifest(), &manifest).map(|_| ()))
+            .and_then(|()| self.record_sizes(stage.path()))
+            .map_err(InstallError::from)
+            .and_then(|()| {
+                exec!(
+                    r#"
+
+        set -euo pipefail
+        tource {pkgfile:?}
 
         if [ "{root}" = "/" ]; then
             if is_function posti; then
@@ -139,57 +271,80 @@ impl Package {
         fi
 
         "#,
-        root = root.unwrap_or("/")
-        )
-        .map_err(|_| InstallError::Execution)?;
+                    root = root.unwrap_or("/")
+                )
+                .map_err(|_| InstallError::Execution)
+            })
+            .and_then(|()| {
+                if update_hooks {
+                    self.run_hook("postu", &update_vars)
+                        .map_err(|_| InstallError::Execution)?;
+                }
+                Ok(())
+            })
+            .and_then(|()| {
+                // Dead files are found through the old IV, so before the new one is written
+                let dead_files = if updating {
+                    find_dead_files(self).unwrap_or_else(|e| {
+                        warn!("Failed to find dead files for {self:-}: {e}");
+                        vec![]
+                    })
+                } else {
+                    vec![]
+                };
+
+                fs::write(&iv, version.rversion())?;
+
+                // Packages are recorded as dependencies unless a reason already exists.
+                // Transactions mark the packages they were asked to install as explicit.
+                if self.install_reason().is_none() {
+                    self.mark(Reason::Dependency)?;
+                }
+                Ok(dead_files)
+            });
+
+        let dead_files = match result {
+            | Ok(d) => d,
+            | Err(e) => {
+                error!("Failed to install {self:-}, rolling back: {e}");
+                stage.rollback();
+                protected.restore();
+                return Err(e)
+            },
+        };
+
+        stage.commit();
 
         // Do some other stuff if updating
         if updating {
-            // TODO: Consider adding update hooks (but wait until needed)
-            if let Err(e) = self.remove_dead_files_after_update() {
-                warn!("Failed to remove dead files for {self:-}: {e}")
-            } else {
-                info!(
-                    "Removed dead files for {}@{}",
-                    self.name,
-                    installed_version.unwrap().srversion()
-                )
-            }
+            self.remove_dead_files_after_update(&dead_files);
+            info!(
+                "Removed dead files for {}@{}",
+                self.name,
+                installed_version.as_ref().unwrap().srversion()
+            );
 
             self.message(suppress, MessageHook::Update);
         } else {
             self.message(suppress, MessageHook::Install);
         }
 
-        // We write the version after removing dead files
-        fs::write(iv, version.rversion())?;
+        self.record_owned();
+        self.log_installing(installed_version, root);
         info!("Installed {self:-}");
-        Ok(())
-    }
 
-    /// # Install a package, ignoring the case where it's already installed
-    ///
-    /// Wraps `install_inner()`
-    ///
-    /// # Arguments
-    /// * `force`       - Whether the package should be forcibly (re)installed
-    /// * `full_force`  - Whether all dependencies should be forcible (re)installed
-    /// * `suppress`    - Whether to suppress messages
-    /// * `root`        - The directory to which the package should be installed (defaults to /)
-    ///
-    /// # Errors
-    // TODO: ^
-    #[instrument(skip(self, force))]
-    pub fn install(
-        &self,
-        force: bool,
-        full_force: bool,
-        suppress: bool,
-        root: Option<&str>,
-    ) -> Result<(), InstallError> {
-        let mut visited = HashSet::new();
-        self.install_inner(force, full_force, &mut visited, suppress, root)
-            .permit(|e| matches!(e, InstallError::AlreadyInstalled))
+        // Replaced packages are removed after this package's IV is written, so its manifest is
+        // considered and only files unique to the replaced package are removed
+        for pkg in replaced {
+            info!("Removing {pkg:-} since {self:-} replaces it");
+            pkg.remove_from(root_path, false, false, suppress)
+                .inspect_err(|e| error!("Failed to remove {pkg:-}, replaced by {self:-}: {e}"))?;
+        }
+
+        for dep in self.missing_optional_deps() {
+            info!("Optional dependency for {}: {dep}", self.name);
+        }
+        Ok(())
     }
 
     /// # Installs a package without its dependencies, ignoring the case where it's already
@@ -214,7 +369,7 @@ impl Package {
         pkg.dependencies = vec![];
 
         let mut visited = HashSet::new();
-        pkg.install_inner(force, false, &mut visited, suppress, root)
+        pkg.install_inner(force, false, &mut visited, suppress, root, &[])
             .permit(|e| matches!(e, InstallError::AlreadyInstalled))
     }
 }
@@ -0,0 +1,347 @@
+// package/manifest.rs
+//! Manifest entries and verification
+//!
+//! A manifest lists every path a package installs, relative to the root. Each line is either a
+//! plain path, as in older manifests, or a tab-separated record:
+//!
+//! ```text
+//! path    type    mode    uid:gid    size    sha256    target
+//! ```
+//!
+//! where `type` is `f`, `d` or `l` as printed by `find -printf %y`, and `sha256` and `target` are
+//! `-` when they don't apply.
+
+use std::{
+    fmt,
+    fs::{
+        Metadata,
+        read_to_string,
+    },
+    io,
+    os::unix::fs::MetadataExt,
+    path::Path,
+};
+
+use tracing::warn;
+
+use super::Package;
+use crate::utils::file::sha256;
+
+/// # The recorded metadata of a manifest entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub kind:   char,
+    pub mode:   u32,
+    pub uid:    u32,
+    pub gid:    u32,
+    pub size:   u64,
+    pub sha256: Option<String>,
+    pub target: Option<String>,
+}
+
+/// # A manifest entry
+///
+/// Plain path lines have no record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub path:   String,
+    pub record: Option<Record>,
+}
+
+/// # Returns the path of a manifest line
+pub fn path_of(line: &str) -> &str { line.split('\t').next().unwrap_or(line) }
+
+/// # Returns the type of a file as printed by `find -printf %y`, or `?` for special files
+fn kind_of(meta: &Metadata) -> char {
+    match meta.file_type() {
+        | t if t.is_dir() => 'd',
+        | t if t.is_symlink() => 'l',
+        | t if t.is_file() => 'f',
+        | _ => '?',
+    }
+}
+
+fn optional(field: &str) -> Option<String> {
+    (field != "-" && !field.is_empty()).then(|| field.to_string())
+}
+
+impl Entry {
+    /// # Parses a manifest line
+    ///
+    /// Malformed records are warned about and treated like plain paths.
+    pub fn parse(line: &str) -> Self {
+        let path = path_of(line).to_string();
+        let record = line.split_once('\t').and_then(|(_, rest)| {
+            let record = Self::parse_record(rest);
+            if record.is_none() {
+                warn!("Malformed manifest record for '{path}'");
+            }
+            record
+        });
+
+        Self { path, record }
+    }
+
+    fn parse_record(fields: &str) -> Option<Record> {
+        let fields = fields.split('\t').collect::<Vec<_>>();
+        let [kind, mode, owner, size, sha256, rest @ ..] = fields.as_slice() else {
+            return None
+        };
+        let (uid, gid) = owner.split_once(':')?;
+
+        Some(Record {
+            kind:   kind.chars().next()?,
+            mode:   u32::from_str_radix(mode, 8).ok()?,
+            uid:    uid.parse().ok()?,
+            gid:    gid.parse().ok()?,
+            size:   size.parse().ok()?,
+            sha256: optional(sha256),
+            target: rest.first().and_then(|t| optional(t)),
+        })
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(r) = &self.record else {
+            return write!(f, "{}", self.path)
+        };
+
+        write!(
+            f,
+            "{}\t{}\t0{:o}\t{}:{}\t{}\t{}\t{}",
+            self.path,
+            r.kind,
+            r.mode,
+            r.uid,
+            r.gid,
+            r.size,
+            r.sha256.as_deref().unwrap_or("-"),
+            r.target.as_deref().unwrap_or("-"),
+        )
+    }
+}
+
+impl Entry {
+    /// # Creates an entry from a path on the filesystem, relative to `root`
+    ///
+    /// # Errors
+    /// - The path doesn't exist, or couldn't be hashed or read
+    pub fn from_path(root: &Path, path: &str) -> io::Result<Self> {
+        let full = root.join(path);
+        let meta = full.symlink_metadata()?;
+        let kind = kind_of(&meta);
+
+        Ok(Self {
+            path:   path.to_string(),
+            record: Some(Record {
+                kind,
+                mode: meta.mode() & 0o7777,
+                uid: meta.uid(),
+                gid: meta.gid(),
+                size: meta.len(),
+                sha256: (kind == 'f').then(|| sha256(&full)).transpose()?,
+                target: (kind == 'l')
+                    .then(|| full.read_link().map(|t| t.to_string_lossy().to_string()))
+                    .transpose()?,
+            }),
+        })
+    }
+}
+
+/// # A discrepancy between a manifest entry and the filesystem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    Missing,
+    Modified(String),
+    Permissions(String),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Self::Missing => write!(f, "missing"),
+            | Self::Modified(m) => write!(f, "modified ({m})"),
+            | Self::Permissions(p) => write!(f, "permissions ({p})"),
+        }
+    }
+}
+
+impl Entry {
+    /// # Checks an entry against the filesystem under `root`
+    ///
+    /// Directories are only checked for existence, since they're shared between packages. Only
+    /// existence is checked for plain path entries. Contents aren't checked for protected files,
+    /// which users are expected to modify.
+    ///
+    /// # Errors
+    /// - A file couldn't be read or hashed
+    pub fn verify(&self, root: &Path, protected: bool) -> io::Result<Vec<Problem>> {
+        let path = root.join(&self.path);
+        let Ok(meta) = path.symlink_metadata() else {
+            return Ok(vec![Problem::Missing])
+        };
+
+        let Some(r) = &self.record else { return Ok(vec![]) };
+
+        let kind = kind_of(&meta);
+        if kind != r.kind {
+            return Ok(vec![Problem::Modified(format!(
+                "type {} -> {kind}",
+                r.kind
+            ))])
+        }
+
+        let mut problems = vec![];
+        match kind {
+            | 'd' => return Ok(problems),
+            | 'l' => {
+                let target = path.read_link()?.to_string_lossy().to_string();
+                if r.target.as_ref().is_some_and(|t| *t != target) {
+                    problems.push(Problem::Modified(format!("target -> {target}")));
+                }
+                return Ok(problems)
+            },
+            | 'f' if !protected => {
+                if meta.len() != r.size {
+                    problems.push(Problem::Modified(format!(
+                        "size {} -> {}",
+                        r.size,
+                        meta.len()
+                    )));
+                } else if let Some(recorded) = &r.sha256
+                    && *recorded != sha256(&path)?
+                {
+                    problems.push(Problem::Modified("contents".to_string()));
+                }
+            },
+            | _ => {},
+        }
+
+        let mode = meta.mode() & 0o7777;
+        if mode != r.mode & 0o7777 {
+            problems.push(Problem::Permissions(format!(
+                "mode {:04o} -> {mode:04o}",
+                r.mode & 0o7777
+            )));
+        }
+        if (meta.uid(), meta.gid()) != (r.uid, r.gid) {
+            problems.push(Problem::Permissions(format!(
+                "owner {}:{} -> {}:{}",
+                r.uid,
+                r.gid,
+                meta.uid(),
+                meta.gid()
+            )));
+        }
+
+        Ok(problems)
+    }
+}
+
+impl Package {
+    /// # Reads the entries of a package's installed manifest
+    ///
+    /// # Errors
+    /// - The package isn't installed
+    /// - The manifest couldn't be read
+    pub fn manifest_entries(&self) -> io::Result<Vec<Entry>> {
+        let manifest = self
+            .manifest()
+            .ok_or(io::Error::from(io::ErrorKind::NotFound))?;
+        Ok(read_to_string(manifest)?
+            .lines()
+            .map(Entry::parse)
+            .collect())
+    }
+
+    /// # Checks a package's installed files against its manifest
+    ///
+    /// Returns the entries with problems, alongside their problems.
+    ///
+    /// # Errors
+    /// - The manifest couldn't be read
+    pub fn verify(&self, root: &Path) -> io::Result<Vec<(Entry, Vec<Problem>)>> {
+        let mut failed = vec![];
+        for entry in self.manifest_entries()? {
+            let problems = entry.verify(root, self.is_protected(&entry.path))?;
+            if !problems.is_empty() {
+                failed.push((entry, problems));
+            }
+        }
+        Ok(failed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        os::unix::fs::PermissionsExt,
+    };
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn parse_entries() {
+        let plain = Entry::parse("usr/bin/foo");
+        assert_eq!(plain.path, "usr/bin/foo");
+        assert!(plain.record.is_none());
+
+        let link = Entry::parse("usr/lib/libfoo.so\tl\t0777\t0:0\t12\t-\tlibfoo.so.1");
+        assert_eq!(link.record.unwrap().target.as_deref(), Some("libfoo.so.1"));
+
+        let file = Entry::parse("usr/bin/foo\tf\t0755\t0:0\t3\tabc\t");
+        let record = file.record.unwrap();
+        assert_eq!((record.kind, record.mode, record.size), ('f', 0o755, 3));
+        assert_eq!(record.sha256.as_deref(), Some("abc"));
+        assert_eq!(record.target, None);
+
+        // Malformed records keep their path
+        let malformed = Entry::parse("usr/bin/foo\tf\tbad");
+        assert_eq!(malformed.path, "usr/bin/foo");
+        assert!(malformed.record.is_none());
+    }
+
+    #[test]
+    fn verify_entries() {
+        let root = tempdir().unwrap();
+        let file = root.path().join("foo");
+        fs::write(&file, "foo").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let meta = file.metadata().unwrap();
+        let line = format!(
+            "foo\tf\t0644\t{}:{}\t3\t{}\t",
+            meta.uid(),
+            meta.gid(),
+            sha256(&file).unwrap()
+        );
+        let entry = Entry::parse(&line);
+        assert!(entry.verify(root.path(), false).unwrap().is_empty());
+
+        fs::write(&file, "bar").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(entry.verify(root.path(), false).unwrap(), vec![
+            Problem::Modified("contents".to_string()),
+            Problem::Permissions("mode 0644 -> 0600".to_string()),
+        ]);
+
+        // Protected files may be modified, but not have their permissions changed
+        assert_eq!(entry.verify(root.path(), true).unwrap(), vec![
+            Problem::Permissions("mode 0644 -> 0600".to_string())
+        ]);
+
+        // Entries created from the filesystem roundtrip and verify cleanly
+        let current = Entry::from_path(root.path(), "foo").unwrap();
+        assert_eq!(Entry::parse(&current.to_string()), current);
+        assert!(current.verify(root.path(), false).unwrap().is_empty());
+
+        fs::remove_file(&file).unwrap();
+        assert_eq!(entry.verify(root.path(), false).unwrap(), vec![
+            Problem::Missing
+        ]);
+    }
+}
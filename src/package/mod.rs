@@ -1,22 +1,40 @@
 pub mod actions;
+pub mod adopt;
 pub mod alias;
+pub mod backup;
 pub mod build;
+pub mod conflict;
 pub mod dep;
 pub mod generate;
 pub mod helpers;
+pub mod index;
 pub mod install;
 pub mod lint;
+pub mod manifest;
 pub mod message;
+pub mod mounts;
+pub mod owners;
+pub mod pkgfile;
+pub mod provides;
 pub mod prune;
 pub mod pull;
+pub mod reason;
 pub mod remove;
+pub mod sandbox;
+pub mod size;
 pub mod source;
+pub mod stage;
+pub mod transaction;
+pub mod trigger;
+pub mod version;
 pub mod vf;
 pub mod view;
 
 use std::{
     fmt,
-    fs::read_to_string, str::FromStr,
+    fs::read_to_string,
+    path::Path,
+    str::FromStr,
 };
 
 use dep::DepKind;
@@ -26,9 +44,9 @@ use serde::{
 };
 use thiserror::Error;
 use tracing::{
+    debug,
     error,
     instrument,
-    warn,
 };
 use walkdir::WalkDir;
 
@@ -38,14 +56,15 @@ use crate::{
             Dep,
             parse_deps,
         },
+        generate::GenerateError,
+        index::Index,
+        pkgfile::Pkgfile,
         remove::is_hidden,
         source::{
             Source,
             parse_sources,
         },
     },
-    sex,
-    utils::parse::us_array,
     utils::commit_hash::try_shorten,
 };
 
@@ -57,6 +76,18 @@ pub enum FormError {
     #[error("Failed to deserialize package")]
     Deserialization(#[from] serde_json::Error),
 
+    #[error("Unsatisfiable dependency: {0}")]
+    Unsatisfiable(String),
+
+    #[error("No package provides {0}")]
+    NoProvider(String),
+
+    #[error("Multiple packages provide {0}, and no preferred provider is configured")]
+    AmbiguousProvider(String),
+
+    #[error("Dependency cycle involving {0}")]
+    DependencyCycle(String),
+
     #[cfg(test)]
     #[error("Missing metadata: {0}")]
     MissingMetadata(String),
@@ -101,11 +132,19 @@ pub enum FormError {
 ///   currently not standardized, though they may be eventually.
 /// * `sources`         - Zero or more dls, or another package. If the dl is not prefixed by a
 ///   character and a comma, explicitly indicating a source kind, the source kind is guessed.
-/// * `dependencies`    - Zero or more dependencies. These may be build-only or always required.
+/// * `dependencies`    - Zero or more dependencies. See `Dep` for the kinds of dependencies.
 /// * `kcfg`            - Zero or more kernel config options required for the correct functioning
 ///   of a package. These are formatted as `option = y/m` or `option_suboption = n`. In other words,
 ///   the `CONFIG_` prefix may be elided, and the yes-module-no tristate can be expressed by the first
 ///   character of those states, delimited by a '/'. For instance, `y/m` means yes or module.
+/// * `provides`        - Zero or more virtual package names this package provides, like `sh` for
+///   bash. Several packages may provide the same name.
+/// * `conflicts`       - Zero or more packages or virtual names that can't be installed alongside
+///   this package.
+/// * `replaces`        - Zero or more packages this package supersedes. They're removed when this
+///   package is installed, and conflict with it.
+/// * `backup`          - Zero or more paths, relative to the root, of configuration files that
+///   shouldn't be overwritten if modified. Files under `etc/` are protected by default.
 #[derive(Deserialize, Serialize, Debug, Clone, Eq, Hash, PartialEq)]
 pub struct Package {
     pub name:       String,
@@ -121,6 +160,14 @@ pub struct Package {
     pub sources:      Vec<Source>,
     pub dependencies: Vec<Dep>,
     pub kcfg:         Vec<String>,
+    #[serde(default)]
+    pub provides:     Vec<String>,
+    #[serde(default)]
+    pub conflicts:    Vec<String>,
+    #[serde(default)]
+    pub replaces:     Vec<String>,
+    #[serde(default)]
+    pub backup:       Vec<String>,
 
     #[serde(skip)]
     pub depkind: Option<DepKind>,
@@ -159,41 +206,23 @@ impl Version {
 
 impl Package {
     /// Creates a new package from its pkg file
+    ///
+    /// The pkg file is parsed natively, falling back to `gen.sh` only if it contains dynamic
+    /// expressions that can't be evaluated without bash.
     #[instrument(level = "debug")]
-    fn new(name: &str) -> Self {
-        let out = sex!("/usr/share/to/scripts/maintainer/gen.sh /var/db/to/pkgs/{name}/pkg").unwrap();
-        let lines = out.lines().map(str::trim).collect::<Vec<_>>();
-
-        let [n, v, r, a, m, l, u, vf, t, s, d, kcfg] = &lines[..] else {
-            panic!("Shouldn't happen lol")
+    fn new(name: &str) -> Result<Self, GenerateError> {
+        let path = format!("/var/db/to/pkgs/{name}/pkg");
+        let contents = read_to_string(&path).map_err(GenerateError::ReadPkgfile)?;
+
+        let pkgfile = match Pkgfile::parse(&contents) {
+            | Err(GenerateError::Dynamic { line, expr }) => {
+                debug!("Falling back to gen.sh for {name} (line {line}: {expr})");
+                Pkgfile::from_gen_sh(path.as_ref())?
+            },
+            | r => r?,
         };
 
-        let u = if u.is_empty() { None } else { Some(u.to_string()) };
-        let r = if r.is_empty() { 1u8 } else { r.parse::<u8>().unwrap_or_else(|_| panic!("Supplied release '{r}' for {n} is not a u8")) };
-        let vr = format!("{v}-{r}");
-
-        let vf = if vf.is_empty() { None } else { Some(vf.to_string()) };
-
-        // NOTE: Tags are not parsed with a unit-separator IFS. This exception exists because tags
-        // should never have spaces and it shortens the pkg syntax.
-        let t = t.split_whitespace().map(|s| s.to_string()).collect();
-        let l = us_array(l);
-        let kcfg = us_array(kcfg);
-
-        Self {
-            name: n.to_string(),
-            version: vr.parse().inspect_err(|e| error!("Failed to parse release version for package '{name}'")).unwrap(),
-            about: a.to_string(),
-            maintainer: m.to_string(),
-            licenses: l,
-            upstream: u,
-            version_fetch: vf,
-            tags: t,
-            sources: parse_sources(s),
-            dependencies: parse_deps(d),
-            kcfg,
-            depkind: None,
-        }
+        pkgfile.try_into()
     }
 
     #[allow(dead_code)]
@@ -215,8 +244,21 @@ impl Package {
         format!("{}-{}", try_shorten(&self.version.version), self.version.release)
     }
 
+    /// # Forms a package from the index, or from its s file if it isn't indexed
+    ///
+    /// Aliases are resolved either way.
     #[instrument(level = "debug")]
     pub fn from_s_file(name: &str) -> Result<Self, FormError> {
+        if let Some(pkg) = Index::cached().and_then(|i| i.resolve(name)) {
+            return Ok(pkg.clone())
+        }
+
+        Self::read_s_file(name)
+    }
+
+    /// # Forms a package by reading its s file, bypassing the index
+    #[instrument(level = "debug")]
+    pub fn read_s_file(name: &str) -> Result<Self, FormError> {
         let s_file = format!("/var/db/to/pkgs/{name}/s");
         let s = read_to_string(&s_file).inspect_err(|e| error!("Failed to read {s_file}: {e}"))?;
         serde_json::from_str(&s).map_err(|e| {
@@ -224,6 +266,69 @@ impl Package {
             FormError::Deserialization(e)
         })
     }
+
+    /// # Forms a package in memory, for tests that shouldn't depend on the package repository
+    #[cfg(test)]
+    pub fn stub(name: &str, dependencies: &[(&str, DepKind)]) -> Self {
+        Self {
+            name:          name.to_string(),
+            version:       Version {
+                version: "1.0".to_string(),
+                release: 1,
+            },
+            about:         String::new(),
+            maintainer:    String::new(),
+            licenses:      Vec::new(),
+            upstream:      None,
+            version_fetch: None,
+            tags:          Vec::new(),
+            sources:       Vec::new(),
+            dependencies:  dependencies
+                .iter()
+                .map(|(name, kind)| Dep {
+                    name:       (*name).to_string(),
+                    kind:       *kind,
+                    constraint: None,
+                })
+                .collect(),
+            kcfg:          Vec::new(),
+            provides:      Vec::new(),
+            conflicts:     Vec::new(),
+            replaces:      Vec::new(),
+            backup:        Vec::new(),
+            depkind:       None,
+        }
+    }
+}
+
+impl TryFrom<Pkgfile> for Package {
+    type Error = GenerateError;
+
+    fn try_from(p: Pkgfile) -> Result<Self, Self::Error> {
+        let nonempty = |s: String| if s.is_empty() { None } else { Some(s) };
+
+        Ok(Self {
+            name:          p.name,
+            version:       Version {
+                version: p.version,
+                release: p.release,
+            },
+            about:         p.about,
+            maintainer:    p.maintainer,
+            licenses:      p.licenses,
+            upstream:      nonempty(p.upstream),
+            version_fetch: nonempty(p.version_fetch),
+            tags:          p.tags,
+            sources:       parse_sources(&p.sources),
+            dependencies:  parse_deps(&p.dependencies)?,
+            kcfg:          p.kcfg,
+            provides:      p.provides,
+            conflicts:     p.conflicts,
+            replaces:      p.replaces,
+            backup:        p.backup,
+            depkind:       None,
+        })
+    }
 }
 
 /// See the documentation for `Package`
@@ -278,6 +383,22 @@ pub fn all_package_names() -> Vec<String> {
         .collect()
 }
 
+/// # Forms every installed package
+///
+/// Installed packages whose s files can't be read, for instance because they were deleted from the
+/// repository, are skipped.
+pub fn installed_packages() -> Vec<Package> {
+    all_package_names()
+        .iter()
+        .filter(|n| Path::new("/var/db/to/data").join(n).join("IV").exists())
+        .filter_map(|n| {
+            Package::from_s_file(n)
+                .inspect_err(|e| error!("Failed to form installed package {n}: {e}"))
+                .ok()
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod test {
     use std::{
@@ -0,0 +1,366 @@
+// package/mounts.rs
+//! Build overlay mounts
+//!
+//! The chroot backend mounts the overlay and API filesystems on the host. Each mount is held by a
+//! guard that unmounts it when dropped, and the guards are kept in a stack so they're unmounted in
+//! reverse order, including when a build fails or panics.
+//!
+//! Active mounts are also tracked globally, so a SIGINT or SIGTERM handler can unmount them before
+//! exiting. Separately from its mounts, a build holds a lock on `.holder` in the chroot from before
+//! it cleans the overlay until its distfile is saved, and writes its pid there. The lock is released
+//! however the build exits, but a build killed any other way leaves stale mounts, which `to health`
+//! and the next build clean up.
+
+use std::{
+    fs::{
+        File,
+        OpenOptions,
+        read_to_string,
+    },
+    io::{
+        self,
+        Write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    process::{
+        self,
+        exit,
+    },
+    sync::{
+        Mutex,
+        MutexGuard,
+        Once,
+        PoisonError,
+    },
+};
+
+use nix::{
+    errno::Errno,
+    fcntl::{
+        Flock,
+        FlockArg,
+    },
+    mount::{
+        MntFlags,
+        MsFlags,
+        mount,
+        umount2,
+    },
+};
+use tracing::{
+    debug,
+    error,
+    info,
+    warn,
+};
+
+use super::build::MERGED;
+
+const HOLDER: &str = "/var/lib/to/chroot/.holder";
+
+/// Mount points held by guards in this process, in mount order
+static ACTIVE: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+static HANDLER: Once = Once::new();
+
+fn active() -> MutexGuard<'static, Vec<PathBuf>> {
+    ACTIVE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// # Lazily unmounts a mount point, warning on failure
+fn unmount(target: &Path) {
+    match umount2(target, MntFlags::MNT_DETACH) {
+        | Ok(()) => debug!("Unmounted {}", target.display()),
+        | Err(e) => warn!("Failed to unmount {}: {e}", target.display()),
+    }
+}
+
+/// # Installs a SIGINT and SIGTERM handler that unmounts active mounts, then exits
+///
+/// The handler runs once per process, and exits with 130 for either signal.
+fn install_handler() {
+    HANDLER.call_once(|| {
+        let result = ctrlc::set_handler(|| {
+            warn!("Interrupted, unmounting the build overlay");
+            let mut active = active();
+            while let Some(target) = active.pop() {
+                unmount(&target);
+            }
+            exit(130)
+        });
+
+        if let Err(e) = result {
+            warn!("Failed to install signal handler: {e}");
+        }
+    });
+}
+
+/// # A mount that's unmounted when dropped
+#[derive(Debug)]
+pub struct Mount {
+    target: PathBuf,
+}
+
+impl Mount {
+    /// # Mounts a filesystem and tracks it until dropped
+    ///
+    /// # Errors
+    /// - The mount failed
+    pub fn new(
+        source: &str,
+        target: &Path,
+        fstype: Option<&str>,
+        flags: MsFlags,
+        data: Option<&str>,
+    ) -> io::Result<Self> {
+        install_handler();
+
+        // The registry is held while mounting so the signal handler can't miss this mount
+        let mut active = active();
+        mount(Some(source), target, fstype, flags, data)
+            .inspect_err(|e| error!("Failed to mount {source} on {}: {e}", target.display()))?;
+        active.push(target.to_path_buf());
+
+        debug!("Mounted {source} on {}", target.display());
+        Ok(Self { target: target.to_path_buf() })
+    }
+}
+
+impl Drop for Mount {
+    fn drop(&mut self) {
+        // The signal handler may have already unmounted it
+        let mut active = active();
+        if let Some(i) = active.iter().rposition(|t| *t == self.target) {
+            active.remove(i);
+            unmount(&self.target);
+        }
+    }
+}
+
+/// # A stack of mounts for a build, unmounted in reverse order when dropped
+#[derive(Debug, Default)]
+pub struct Mounts {
+    mounts: Vec<Mount>,
+}
+
+impl Mounts {
+    /// # Mounts a filesystem on top of the stack
+    ///
+    /// # Errors
+    /// - The mount failed
+    pub fn mount(
+        &mut self,
+        source: &str,
+        target: &Path,
+        fstype: Option<&str>,
+        flags: MsFlags,
+        data: Option<&str>,
+    ) -> io::Result<()> {
+        self.mounts
+            .push(Mount::new(source, target, fstype, flags, data)?);
+        Ok(())
+    }
+}
+
+impl Drop for Mounts {
+    fn drop(&mut self) {
+        while let Some(mount) = self.mounts.pop() {
+            drop(mount);
+        }
+    }
+}
+
+/// # A lock on the build overlay, held by a build until its distfile is saved
+///
+/// Nothing else cleans or mounts the overlay while this is held. It's released when dropped.
+#[derive(Debug)]
+pub struct Holder {
+    _lock: Flock<File>,
+}
+
+impl Holder {
+    /// # Locks the build overlay for this process
+    ///
+    /// # Errors
+    /// - Another build holds the build overlay
+    /// - The holder couldn't be locked or written
+    pub fn acquire() -> io::Result<Self> {
+        lock_holder(Path::new(HOLDER))
+            .inspect_err(|_| {
+                if let Some(pid) = holder() {
+                    error!("The build overlay is in use by another build ({pid})");
+                }
+            })
+            .map(|lock| Self { _lock: lock })
+    }
+}
+
+/// # Locks a holder file for this process and writes its pid
+fn lock_holder(path: &Path) -> io::Result<Flock<File>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    let mut holder = Flock::lock(file, FlockArg::LockExclusiveNonblock).map_err(|(_, e)| {
+        error!("Failed to lock {}: {e}", path.display());
+        io::Error::from(e)
+    })?;
+
+    holder.set_len(0)?;
+    write!(holder, "{}", process::id())?;
+    Ok(holder)
+}
+
+/// # Returns the pid of the process holding a lock on a holder file, if any
+fn locked_by(path: &Path) -> Option<u32> {
+    let file = File::open(path).ok()?;
+    match Flock::lock(file, FlockArg::LockSharedNonblock) {
+        | Err((_, Errno::EWOULDBLOCK)) => {
+            // The pid may not be written yet if the lock was just taken
+            Some(
+                read_to_string(path)
+                    .ok()?
+                    .trim()
+                    .parse()
+                    .unwrap_or_default(),
+            )
+        },
+        | Err((_, e)) => {
+            warn!("Failed to check the lock on {}: {e}", path.display());
+            None
+        },
+        | Ok(_) => None,
+    }
+}
+
+/// # Returns the pid of the build holding the overlay, if any
+///
+/// A build holds the overlay for as long as it has the holder locked, so a holder left behind by a
+/// killed build is never mistaken for a running one.
+pub fn holder() -> Option<u32> { locked_by(Path::new(HOLDER)) }
+
+/// # Decodes the octal escapes `/proc/self/mountinfo` uses for whitespace and backslashes
+fn unescape(field: &str) -> String {
+    let mut out = Vec::with_capacity(field.len());
+    let bytes = field.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && let Some(byte) = field
+                .get(i + 1..i + 4)
+                .and_then(|o| u8::from_str_radix(o, 8).ok())
+        {
+            out.push(byte);
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+/// # Lists the mount points at or under a directory, deepest first
+fn mounts_under(dir: &Path, mountinfo: &str) -> Vec<PathBuf> {
+    let mut mounts = mountinfo
+        .lines()
+        .filter_map(|l| l.split(' ').nth(4))
+        .map(|m| PathBuf::from(unescape(m)))
+        .filter(|m| m.starts_with(dir))
+        .collect::<Vec<_>>();
+
+    // Mounts are listed in the order they were made, so later ones may sit on earlier ones
+    mounts.reverse();
+    mounts
+}
+
+/// # Finds mounts under the build overlay left behind by a build that's no longer running
+///
+/// # Errors
+/// - The mount table couldn't be read
+pub fn stale_mounts() -> io::Result<Vec<PathBuf>> {
+    if let Some(pid) = holder() {
+        debug!("The build overlay is held by {pid}");
+        return Ok(Vec::new())
+    }
+
+    Ok(mounts_under(
+        Path::new(MERGED),
+        &read_to_string("/proc/self/mountinfo")?,
+    ))
+}
+
+/// # Unmounts stale mounts under the build overlay
+///
+/// With the overlay held, every mount under it is stale. Returns the number of mounts that couldn't
+/// be unmounted.
+///
+/// # Errors
+/// - The mount table couldn't be read
+pub fn clean_stale_mounts(_holder: &Holder) -> io::Result<usize> {
+    let stale = mounts_under(Path::new(MERGED), &read_to_string("/proc/self/mountinfo")?);
+    if stale.is_empty() {
+        return Ok(0)
+    }
+
+    info!("Unmounting {} stale mount(s) under {MERGED}", stale.len());
+    let failed = stale
+        .iter()
+        .filter(|m| {
+            umount2(*m, MntFlags::MNT_DETACH)
+                .inspect_err(|e| warn!("Failed to unmount {}: {e}", m.display()))
+                .is_err()
+        })
+        .count();
+
+    Ok(failed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_mountinfo() {
+        let mountinfo = "\
+22 1 0:21 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+40 22 0:35 / /var/lib/to/chroot/merged rw - overlay overlay rw,lowerdir=lower
+41 40 0:5 / /var/lib/to/chroot/merged/dev rw - devtmpfs devtmpfs rw
+42 41 0:22 / /var/lib/to/chroot/merged/dev/pts rw - devpts devpts rw
+43 22 0:40 / /mnt/my\\040disk rw - ext4 /dev/sdb1 rw
+";
+
+        assert_eq!(
+            mounts_under(Path::new(MERGED), mountinfo),
+            [
+                "/var/lib/to/chroot/merged/dev/pts",
+                "/var/lib/to/chroot/merged/dev",
+                "/var/lib/to/chroot/merged",
+            ]
+            .map(PathBuf::from)
+        );
+        assert_eq!(mounts_under(Path::new("/mnt"), mountinfo), [PathBuf::from(
+            "/mnt/my disk"
+        )]);
+    }
+
+    #[test]
+    fn holder_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".holder");
+
+        // A holder left behind without a lock, even with a live pid, isn't held
+        std::fs::write(&path, process::id().to_string()).unwrap();
+        assert_eq!(locked_by(&path), None);
+
+        let lock = lock_holder(&path).unwrap();
+        assert_eq!(locked_by(&path), Some(process::id()));
+        assert!(lock_holder(&path).is_err());
+
+        drop(lock);
+        assert_eq!(locked_by(&path), None);
+    }
+}
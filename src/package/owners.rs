@@ -0,0 +1,323 @@
+// package/owners.rs
+//! Reverse file-ownership index
+//!
+//! Maps every path in an installed manifest to the packages that install it. The index is stored
+//! at `/var/db/to/data/.owners`, updated on install and removal, and rebuilt from the installed
+//! manifests if it's missing or invalid.
+
+use std::{
+    collections::{
+        BTreeMap,
+        BTreeSet,
+    },
+    fs::{
+        self,
+        read_to_string,
+        rename,
+    },
+    io,
+    path::{
+        Path,
+        PathBuf,
+        absolute,
+    },
+};
+
+use glob::Pattern;
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tracing::{
+    debug,
+    info,
+    instrument,
+    warn,
+};
+use walkdir::{
+    DirEntry,
+    WalkDir,
+};
+
+use super::{
+    Package,
+    Version,
+    remove::{
+        KEPT,
+        locate,
+        read_all_manifests,
+    },
+};
+use crate::CONFIG;
+
+pub const OWNERS: &str = "/var/db/to/data/.owners";
+
+/// # The ownership index
+///
+/// # Fields
+/// * `paths`           - Every installed path, relative to the root, mapped to the names of the
+///   packages that install it.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Owners {
+    pub paths: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// # Returns the name of the package a manifest in the data directory belongs to
+pub fn owner_of_manifest(manifest: &Path) -> String {
+    manifest
+        .parent()
+        .and_then(Path::file_name)
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// # Checks whether a manifest in the data directory is for its package's installed version
+fn is_current_manifest(manifest: &Path) -> bool {
+    read_to_string(manifest.with_file_name("IV"))
+        .ok()
+        .and_then(|iv| iv.trim().parse::<Version>().ok())
+        .is_some_and(|iv| {
+            manifest.file_name() == Some(format!("MANIFEST@{}", iv.srversion()).as_ref())
+        })
+}
+
+impl Owners {
+    /// # Builds the index from every installed manifest
+    ///
+    /// Manifests left behind by older versions are skipped, so they can't shadow the current one.
+    ///
+    /// # Errors
+    /// - A manifest couldn't be read
+    #[instrument]
+    pub fn build() -> io::Result<Self> { Self::build_from(Path::new("/var/db/to/data")) }
+
+    /// # Builds the index from the current manifests in a data directory
+    ///
+    /// # Errors
+    /// - A manifest couldn't be read
+    pub fn build_from(data: &Path) -> io::Result<Self> {
+        let manifests = locate(data, 2)
+            .into_iter()
+            .filter(|m| is_current_manifest(m))
+            .collect::<Vec<_>>();
+
+        let mut owners = Self::default();
+        for (manifest, paths) in read_all_manifests(&manifests)? {
+            owners.record(&owner_of_manifest(&manifest), paths);
+        }
+        Ok(owners)
+    }
+
+    /// # Loads the index for a root
+    ///
+    /// The index is used for /, and other roots are built from the current manifests in their data
+    /// directory.
+    ///
+    /// # Errors
+    /// - The index or a manifest couldn't be read
+    pub fn for_root(root: &Path) -> io::Result<Self> {
+        if root == Path::new("/") {
+            Self::load()
+        } else {
+            Self::build_from(&root.join("var/db/to/data"))
+        }
+    }
+
+    /// # Writes the index to disk
+    ///
+    /// The index is written to a part file first, then moved into place.
+    pub fn write(&self) -> io::Result<()> {
+        let part = format!("{OWNERS}.part");
+        fs::write(&part, serde_json::to_string(self)?)?;
+        rename(part, OWNERS)?;
+
+        debug!("Wrote ownership index with {} paths", self.paths.len());
+        Ok(())
+    }
+
+    /// # Loads the index from disk, rebuilding it if it's missing or invalid
+    ///
+    /// # Errors
+    /// - The index had to be rebuilt, and a manifest couldn't be read or the index couldn't be
+    ///   written
+    pub fn load() -> io::Result<Self> {
+        let loaded = read_to_string(OWNERS)
+            .inspect_err(|e| debug!("Failed to read ownership index: {e}"))
+            .ok()
+            .and_then(|c| {
+                serde_json::from_str(&c)
+                    .inspect_err(|e| warn!("Failed to deserialize ownership index: {e}"))
+                    .ok()
+            });
+
+        if let Some(owners) = loaded {
+            return Ok(owners)
+        }
+
+        info!("Rebuilding ownership index");
+        let owners = Self::build()?;
+        owners.write()?;
+        Ok(owners)
+    }
+
+    /// # Loads the index, applies a change, and writes it back
+    ///
+    /// Failures are warned about, since the index can always be rebuilt.
+    pub fn update(change: impl FnOnce(&mut Self)) {
+        let result = Self::load().and_then(|mut owners| {
+            change(&mut owners);
+            owners.write()
+        });
+
+        if let Err(e) = result {
+            warn!("Failed to update ownership index: {e}");
+            warn!("Rebuild it with `to owns --rebuild`");
+        }
+    }
+
+    /// # Returns the packages that install a path, relative to the root
+    pub fn owners_of(&self, path: &str) -> Option<&BTreeSet<String>> {
+        self.paths.get(path.trim_matches('/'))
+    }
+
+    /// # Records the paths a package installs, replacing any previously recorded
+    pub fn record(&mut self, pkg: &str, paths: impl IntoIterator<Item = String>) {
+        self.forget(pkg);
+        for path in paths {
+            self.paths.entry(path).or_default().insert(pkg.to_string());
+        }
+    }
+
+    /// # Removes a package from the index
+    pub fn forget(&mut self, pkg: &str) {
+        self.paths.retain(|_, owners| {
+            owners.remove(pkg);
+            !owners.is_empty()
+        });
+    }
+}
+
+/// # Finds files and symlinks under a directory that no installed package owns
+///
+/// Paths matching `CONFIG.untracked_ignore`, and paths that are never removed, are skipped.
+/// Ignored directories aren't descended into. A relative `under` is resolved against the current
+/// directory, and the returned paths are absolute.
+///
+/// # Errors
+/// - `under` couldn't be made absolute
+/// - The ownership index couldn't be loaded
+pub fn find_untracked(under: &Path) -> io::Result<Vec<PathBuf>> {
+    let under = absolute(under)?;
+    let owners = Owners::load()?;
+    let ignored = CONFIG
+        .untracked_ignore
+        .iter()
+        .filter_map(|g| {
+            Pattern::new(g.trim_matches('/'))
+                .inspect_err(|e| warn!("Invalid untracked ignore glob '{g}': {e}"))
+                .ok()
+        })
+        .collect::<Vec<_>>();
+
+    Ok(untracked(&under, &owners, &ignored))
+}
+
+fn untracked(under: &Path, owners: &Owners, ignored: &[Pattern]) -> Vec<PathBuf> {
+    let rel = |p: &Path| p.to_string_lossy().trim_start_matches('/').to_string();
+
+    WalkDir::new(under)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| !ignored.iter().any(|g| g.matches(&rel(e.path()))))
+        .filter_map(|e| e.inspect_err(|e| debug!("Skipping unreadable entry: {e}")).ok())
+        .filter(|e| !e.file_type().is_dir())
+        .filter(|e| !KEPT.iter().any(|k| e.path() == Path::new(k)))
+        .filter(|e| owners.owners_of(&rel(e.path())).is_none())
+        .map(DirEntry::into_path)
+        .collect()
+}
+
+impl Package {
+    /// # Records a package's installed manifest in the ownership index
+    pub fn record_owned(&self) {
+        let paths = match self.manifest_entries() {
+            | Ok(entries) => entries.into_iter().map(|e| e.path),
+            | Err(e) => return warn!("Failed to read manifest for {self:-}: {e}"),
+        };
+
+        Owners::update(|o| o.record(&self.name, paths));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn record_and_forget() {
+        let mut owners = Owners::default();
+        owners.record("coreutils", ["usr", "usr/bin", "usr/bin/ls"].map(String::from));
+        owners.record("nvi", ["usr", "usr/bin", "usr/bin/vi", "usr/bin/ex"].map(String::from));
+
+        assert_eq!(owners.owners_of("/usr/bin").unwrap().len(), 2);
+        assert!(owners.owners_of("usr/bin/vi").unwrap().contains("nvi"));
+
+        // Recording again replaces a package's paths
+        owners.record("nvi", ["usr", "usr/bin", "usr/bin/vi"].map(String::from));
+        assert!(owners.owners_of("usr/bin/ex").is_none());
+
+        owners.forget("nvi");
+        assert!(owners.owners_of("usr/bin/vi").is_none());
+        assert_eq!(owners.owners_of("usr/bin").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn current_manifests() {
+        let data = tempfile::tempdir().unwrap();
+        let data = data.path();
+        fs::write(data.join("IV"), "1.2.0-2\n").unwrap();
+
+        assert!(is_current_manifest(&data.join("MANIFEST@1.2.0-2")));
+        assert!(!is_current_manifest(&data.join("MANIFEST@1.1.0-1")));
+        assert!(!is_current_manifest(&data.join("MANIFEST@1.2.0-1")));
+    }
+
+    #[test]
+    /// A path that moved between packages belongs only to its current owner
+    fn build_from_current_manifests() {
+        let data = tempfile::Builder::new().prefix("to-data").tempdir().unwrap();
+        let write = |pkg: &str, file: &str, contents: &str| {
+            fs::create_dir_all(data.path().join(pkg)).unwrap();
+            fs::write(data.path().join(pkg).join(file), contents).unwrap();
+        };
+
+        write("x", "IV", "2.0-1");
+        write("x", "MANIFEST@1.0-1", "usr\nusr/bin\nusr/bin/tool\n");
+        write("x", "MANIFEST@2.0-1", "usr\nusr/bin\n");
+        write("y", "IV", "1.0-1");
+        write("y", "MANIFEST@1.0-1", "usr\nusr/bin\nusr/bin/tool\n");
+
+        let owners = Owners::build_from(data.path()).unwrap();
+        assert_eq!(owners.owners_of("usr/bin/tool").unwrap(), &BTreeSet::from(["y".to_string()]));
+        assert_eq!(owners.owners_of("usr/bin").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn untracked_files() {
+        let root = tempfile::tempdir().unwrap();
+        let under = root.path().join("usr");
+        for f in ["bin/ls", "bin/stray", "share/cache/junk"] {
+            let f = under.join(f);
+            fs::create_dir_all(f.parent().unwrap()).unwrap();
+            fs::write(f, "").unwrap();
+        }
+
+        let rel = |f: &str| under.join(f).to_string_lossy().trim_start_matches('/').to_string();
+        let mut owners = Owners::default();
+        owners.record("coreutils", [rel("bin"), rel("bin/ls")]);
+        let ignored = [Pattern::new(&rel("share/cache")).unwrap()];
+
+        assert_eq!(untracked(&under, &owners, &ignored), vec![under.join("bin/stray")]);
+    }
+}
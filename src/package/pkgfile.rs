@@ -0,0 +1,675 @@
+// package/pkgfile.rs
+//! Native parser for pkg files
+//!
+//! Pkg files are bash scripts, but the metadata they define is almost always a handful of static
+//! assignments. Those assignments are parsed here directly, which avoids forking bash for every
+//! package. Anything that can't be evaluated statically (command substitutions, parameter
+//! expansion operators, unknown variables, top-level commands, etc.) is reported as
+//! `GenerateError::Dynamic`, and the caller should fall back to `gen.sh`.
+
+use std::{
+    collections::HashMap,
+    path::Path,
+};
+
+use tracing::trace;
+
+use super::generate::GenerateError;
+use crate::{
+    sex,
+    utils::parse::us_array,
+};
+
+/// # The raw metadata defined by a pkg file
+///
+/// Fields are unprocessed, mirroring the output of `gen.sh`. Sources and dependencies are parsed
+/// when the pkg file is converted into a `Package`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Pkgfile {
+    pub name:          String,
+    pub version:       String,
+    pub release:       u8,
+    pub about:         String,
+    pub maintainer:    String,
+    pub licenses:      Vec<String>,
+    pub upstream:      String,
+    pub version_fetch: String,
+    pub tags:          Vec<String>,
+    pub sources:       Vec<String>,
+    pub dependencies:  Vec<String>,
+    pub kcfg:          Vec<String>,
+    pub provides:      Vec<String>,
+    pub conflicts:     Vec<String>,
+    pub replaces:      Vec<String>,
+    pub backup:        Vec<String>,
+}
+
+/// # A bash variable
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Scalar(String),
+    Array(Vec<String>),
+}
+
+impl Value {
+    /// Mimics `$x`, which for arrays is the first element
+    fn scalar(&self) -> &str {
+        match self {
+            | Value::Scalar(s) => s,
+            | Value::Array(a) => a.first().map_or("", String::as_str),
+        }
+    }
+
+    /// Mimics `${x[*]}` split on the unit separator, with empty fields removed
+    fn array(&self) -> Vec<String> {
+        match self {
+            | Value::Scalar(s) if s.is_empty() => vec![],
+            | Value::Scalar(s) => vec![s.clone()],
+            | Value::Array(a) => a.iter().filter(|s| !s.is_empty()).cloned().collect(),
+        }
+    }
+}
+
+impl Pkgfile {
+    /// # Parses the contents of a pkg file
+    ///
+    /// The first line is the header (`name@version-release`). The rest of the file is treated as
+    /// bash, of which only variable assignments and function definitions are understood. Function
+    /// bodies are skipped.
+    ///
+    /// # Errors
+    /// - `GenerateError::MissingHeader` if the header is absent
+    /// - `GenerateError::InvalidRelease` if the release isn't a u8
+    /// - `GenerateError::Syntax` for unterminated quotes, arrays, or functions
+    /// - `GenerateError::Dynamic` for anything that must be evaluated by bash
+    pub fn parse(contents: &str) -> Result<Self, GenerateError> {
+        let (header, body) = contents.split_once('\n').unwrap_or((contents, ""));
+        let header = header.trim();
+
+        let Some((name, vr)) = header.rsplit_once('@') else {
+            return Err(GenerateError::MissingHeader)
+        };
+
+        if name.is_empty() || vr.is_empty() {
+            return Err(GenerateError::MissingHeader)
+        }
+
+        let (version, release) = vr.rsplit_once('-').unwrap_or((vr, "1"));
+        let release = parse_release(release, 1)?;
+
+        let mut parser = Parser::new(body);
+        parser.set("n", Value::Scalar(name.to_string()));
+        parser.set("v", Value::Scalar(version.to_string()));
+        parser.set("r", Value::Scalar(release.to_string()));
+        parser.parse()?;
+
+        let get = |k: &str| parser.vars.get(k);
+        let scalar = |k: &str| get(k).map(|v| v.scalar().to_string()).unwrap_or_default();
+        let array = |k: &str| get(k).map(Value::array).unwrap_or_default();
+
+        // NOTE: Tags are split on whitespace rather than the unit separator, matching `gen.sh`.
+        let tags = array("t")
+            .join(" ")
+            .split_whitespace()
+            .map(String::from)
+            .collect();
+
+        Ok(Self {
+            name: name.to_string(),
+            version: version.to_string(),
+            release,
+            about: scalar("a"),
+            maintainer: scalar("m"),
+            licenses: array("l"),
+            upstream: scalar("u"),
+            version_fetch: scalar("vf"),
+            tags,
+            sources: array("s"),
+            dependencies: array("d"),
+            kcfg: array("kcfg"),
+            provides: array("p"),
+            conflicts: array("conflicts"),
+            replaces: array("replaces"),
+            backup: array("bk"),
+        })
+    }
+
+    /// # Evaluates a pkg file with `gen.sh`
+    ///
+    /// This is the fallback for pkg files that can't be parsed natively.
+    pub fn from_gen_sh(pkgfile: &Path) -> Result<Self, GenerateError> {
+        let out = sex!(
+            "/usr/share/to/scripts/maintainer/gen.sh {}",
+            pkgfile.display()
+        )
+        .map_err(GenerateError::Fallback)?;
+
+        Self::from_gen_sh_output(&out)
+    }
+
+    /// # Parses the sixteen lines output by `gen.sh`
+    fn from_gen_sh_output(out: &str) -> Result<Self, GenerateError> {
+        let lines = out.lines().map(str::trim).collect::<Vec<_>>();
+
+        let [n, v, r, a, m, l, u, vf, t, s, d, kcfg, p, conflicts, replaces, bk] = &lines[..] else {
+            return Err(GenerateError::FallbackOutput(lines.len()))
+        };
+
+        let release = if r.is_empty() { 1 } else { parse_release(r, 1)? };
+
+        Ok(Self {
+            name: n.to_string(),
+            version: v.to_string(),
+            release,
+            about: a.to_string(),
+            maintainer: m.to_string(),
+            licenses: us_array(l),
+            upstream: u.to_string(),
+            version_fetch: vf.to_string(),
+            tags: t.split_whitespace().map(String::from).collect(),
+            sources: us_array(s),
+            dependencies: us_array(d),
+            kcfg: us_array(kcfg),
+            provides: us_array(p),
+            conflicts: us_array(conflicts),
+            replaces: us_array(replaces),
+            backup: us_array(bk),
+        })
+    }
+}
+
+fn parse_release(release: &str, line: usize) -> Result<u8, GenerateError> {
+    release.parse().map_err(|_| GenerateError::InvalidRelease {
+        line,
+        release: release.to_string(),
+    })
+}
+
+/// # A minimal parser for the subset of bash used by pkg files
+struct Parser {
+    chars: Vec<char>,
+    pos:   usize,
+    /// The current line number, starting at 2 since the header is not part of the body
+    line:  usize,
+    vars:  HashMap<String, Value>,
+}
+
+impl Parser {
+    fn new(body: &str) -> Self {
+        Self {
+            chars: body.chars().collect(),
+            pos:   0,
+            line:  2,
+            vars:  HashMap::new(),
+        }
+    }
+
+    fn set(&mut self, name: &str, value: Value) { self.vars.insert(name.to_string(), value); }
+
+    fn peek(&self) -> Option<char> { self.chars.get(self.pos).copied() }
+
+    fn peek_at(&self, offset: usize) -> Option<char> { self.chars.get(self.pos + offset).copied() }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn syntax(&self, line: usize, reason: &str) -> GenerateError {
+        GenerateError::Syntax {
+            line,
+            reason: reason.to_string(),
+        }
+    }
+
+    /// Returns a `Dynamic` error containing the current line
+    fn dynamic(&self, line: usize) -> GenerateError {
+        let start = self.chars[..self.pos]
+            .iter()
+            .rposition(|&c| c == '\n')
+            .map_or(0, |i| i + 1);
+        let end = self.chars[self.pos..]
+            .iter()
+            .position(|&c| c == '\n')
+            .map_or(self.chars.len(), |i| self.pos + i);
+
+        GenerateError::Dynamic {
+            line,
+            expr: self.chars[start..end].iter().collect::<String>().trim().to_string(),
+        }
+    }
+
+    /// Skips spaces, tabs, and line continuations
+    fn skip_blank(&mut self) {
+        loop {
+            match self.peek() {
+                | Some(' ' | '\t') => {
+                    self.bump();
+                },
+                | Some('\\') if self.peek_at(1) == Some('\n') => {
+                    self.bump();
+                    self.bump();
+                },
+                | _ => break,
+            }
+        }
+    }
+
+    /// Skips a comment, if one starts here
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            self.skip_line();
+        }
+    }
+
+    /// Skips to the end of the current line
+    fn skip_line(&mut self) {
+        while self.peek().is_some_and(|c| c != '\n') {
+            self.bump();
+        }
+    }
+
+    /// Skips whitespace, newlines, and comments
+    fn skip_trivia(&mut self) {
+        loop {
+            self.skip_blank();
+            self.skip_comment();
+            match self.peek() {
+                | Some('\n') => {
+                    self.bump();
+                },
+                | _ => break,
+            }
+        }
+    }
+
+    fn read_ident(&mut self) -> String {
+        let mut ident = String::new();
+        while let Some(c) = self.peek() {
+            let valid = c == '_' || c.is_ascii_alphabetic() || (!ident.is_empty() && c.is_ascii_digit());
+            if !valid {
+                break
+            }
+            ident.push(c);
+            self.bump();
+        }
+        ident
+    }
+
+    fn parse(&mut self) -> Result<(), GenerateError> {
+        loop {
+            self.skip_trivia();
+            if self.peek().is_none() {
+                return Ok(())
+            }
+
+            // Statement separators
+            if self.peek() == Some(';') {
+                self.bump();
+                continue
+            }
+
+            self.parse_statement()?;
+        }
+    }
+
+    fn parse_statement(&mut self) -> Result<(), GenerateError> {
+        let line = self.line;
+        let ident = self.read_ident();
+
+        if ident.is_empty() {
+            return Err(self.dynamic(line))
+        }
+
+        match (self.peek(), self.peek_at(1)) {
+            | (Some('='), _) => {
+                self.bump();
+                let value = self.parse_value()?;
+                self.vars.insert(ident, value);
+            },
+            | (Some('+'), Some('=')) => {
+                self.bump();
+                self.bump();
+                let value = self.parse_value()?;
+                let appended = match (self.vars.remove(&ident), value) {
+                    | (None, v) => v,
+                    | (Some(Value::Scalar(a)), Value::Scalar(b)) => Value::Scalar(a + &b),
+                    | (Some(old), new) => {
+                        let mut old = match old {
+                            | Value::Scalar(s) => vec![s],
+                            | Value::Array(a) => a,
+                        };
+                        match new {
+                            | Value::Scalar(s) => old.push(s),
+                            | Value::Array(mut a) => old.append(&mut a),
+                        }
+                        Value::Array(old)
+                    },
+                };
+                self.vars.insert(ident, appended);
+            },
+            | _ if ident == "function" => {
+                self.skip_blank();
+                self.read_ident();
+                self.skip_blank();
+                if self.peek() == Some('(') && self.peek_at(1) == Some(')') {
+                    self.bump();
+                    self.bump();
+                }
+                return self.skip_function(line)
+            },
+            | _ => {
+                self.skip_blank();
+                if self.peek() == Some('(') && self.peek_at(1) == Some(')') {
+                    self.bump();
+                    self.bump();
+                    return self.skip_function(line)
+                }
+                return Err(self.dynamic(line))
+            },
+        }
+
+        // Anything following an assignment on the same line (aside from a comment or separator)
+        // means the assignment was an environment prefix for a command
+        self.skip_blank();
+        match self.peek() {
+            | None | Some('\n' | ';' | '#') => Ok(()),
+            | _ => Err(self.dynamic(line)),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, GenerateError> {
+        if self.peek() != Some('(') {
+            return Ok(Value::Scalar(self.parse_word(false)?))
+        }
+
+        let line = self.line;
+        self.bump();
+
+        let mut words = Vec::new();
+        loop {
+            self.skip_trivia();
+            match self.peek() {
+                | None => return Err(self.syntax(line, "unterminated array")),
+                | Some(')') => {
+                    self.bump();
+                    return Ok(Value::Array(words))
+                },
+                | Some(';') => return Err(self.dynamic(self.line)),
+                | _ => words.push(self.parse_word(true)?),
+            }
+        }
+    }
+
+    /// # Parses a single shell word
+    ///
+    /// Quotes are removed and known variables are expanded. Unquoted expansions whose values would
+    /// be word split are considered dynamic when `in_array` is set.
+    fn parse_word(&mut self, in_array: bool) -> Result<String, GenerateError> {
+        let line = self.line;
+        let mut word = String::new();
+
+        while let Some(c) = self.peek() {
+            match c {
+                | ' ' | '\t' | '\n' | ';' | ')' => break,
+                | '|' | '&' | '<' | '>' | '(' | '`' | '*' | '?' | '[' | '{' =>
+                    return Err(self.dynamic(self.line)),
+                | '~' if word.is_empty() => return Err(self.dynamic(self.line)),
+                | '\\' => {
+                    self.bump();
+                    match self.bump() {
+                        | Some('\n') => {},
+                        | Some(c) => word.push(c),
+                        | None => word.push('\\'),
+                    }
+                },
+                | '\'' => {
+                    self.bump();
+                    loop {
+                        match self.bump() {
+                            | Some('\'') => break,
+                            | Some(c) => word.push(c),
+                            | None => return Err(self.syntax(line, "unterminated single quote")),
+                        }
+                    }
+                },
+                | '"' => {
+                    self.bump();
+                    self.parse_double_quoted(&mut word, line)?;
+                },
+                | '$' => {
+                    self.bump();
+                    let expanded = self.parse_expansion()?;
+                    if in_array && expanded.chars().any(char::is_whitespace) {
+                        return Err(self.dynamic(self.line))
+                    }
+                    word.push_str(&expanded);
+                },
+                | c => {
+                    self.bump();
+                    word.push(c);
+                },
+            }
+        }
+
+        trace!("Parsed word '{word}' on line {line}");
+        Ok(word)
+    }
+
+    fn parse_double_quoted(&mut self, word: &mut String, line: usize) -> Result<(), GenerateError> {
+        loop {
+            match self.bump() {
+                | None => return Err(self.syntax(line, "unterminated double quote")),
+                | Some('"') => return Ok(()),
+                | Some('\\') => match self.bump() {
+                    | Some('\n') => {},
+                    | Some(c @ ('$' | '`' | '"' | '\\')) => word.push(c),
+                    | Some(c) => {
+                        word.push('\\');
+                        word.push(c);
+                    },
+                    | None => return Err(self.syntax(line, "unterminated double quote")),
+                },
+                | Some('$') => {
+                    let expanded = self.parse_expansion()?;
+                    word.push_str(&expanded);
+                },
+                | Some('`') => return Err(self.dynamic(self.line)),
+                | Some(c) => word.push(c),
+            }
+        }
+    }
+
+    /// # Expands a variable following a `$`
+    ///
+    /// Only `$name` and `${name}` are supported, and only for variables previously assigned in the
+    /// pkg file (or `n`, `v`, and `r` from the header). A lone `$` is kept literally.
+    fn parse_expansion(&mut self) -> Result<String, GenerateError> {
+        let line = self.line;
+
+        let name = match self.peek() {
+            | Some('{') => {
+                self.bump();
+                let name = self.read_ident();
+                if name.is_empty() || self.peek() != Some('}') {
+                    return Err(self.dynamic(line))
+                }
+                self.bump();
+                name
+            },
+            | Some(c) if c == '_' || c.is_ascii_alphabetic() => self.read_ident(),
+            | Some(c) if c.is_whitespace() || c == '"' => return Ok("$".to_string()),
+            | None => return Ok("$".to_string()),
+            | _ => return Err(self.dynamic(line)),
+        };
+
+        match self.vars.get(&name) {
+            | Some(v) => Ok(v.scalar().to_string()),
+            | None => Err(self.dynamic(line)),
+        }
+    }
+
+    /// # Skips over a function body
+    ///
+    /// Braces are counted, ignoring those in quotes and comments.
+    fn skip_function(&mut self, line: usize) -> Result<(), GenerateError> {
+        self.skip_trivia();
+        if self.peek() != Some('{') {
+            return Err(self.dynamic(self.line))
+        }
+
+        let mut depth = 0usize;
+        let mut prev = ' ';
+        while let Some(c) = self.bump() {
+            match c {
+                | '\\' => {
+                    self.bump();
+                },
+                | '\'' => {
+                    while self.bump().is_some_and(|c| c != '\'') {}
+                },
+                | '"' => {
+                    while let Some(c) = self.bump() {
+                        match c {
+                            | '\\' => {
+                                self.bump();
+                            },
+                            | '"' => break,
+                            | _ => {},
+                        }
+                    }
+                },
+                | '#' if prev.is_whitespace() || prev == ';' => self.skip_line(),
+                | '{' => depth += 1,
+                | '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(())
+                    }
+                },
+                | _ => {},
+            }
+            prev = c;
+        }
+
+        Err(self.syntax(line, "unterminated function"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_template_like() {
+        let contents = r#"foo@1.2.3-2
+
+a="A package for testing"
+m="Toxikuu"
+l="MIT"
+t="lib test"
+u="https://github.com/toxikuu/foo.git"
+
+s=(
+    "https://github.com/toxikuu/foo/archive/v$v.tar.gz -> foo-$v.tgz"
+    'p,linux'
+)
+
+d=(
+    glibc
+    b,meson # build only
+)
+
+p=(libfoo foo-compat)
+conflicts=(bar)
+replaces=(oldfoo)
+bk=(etc/foo.conf)
+
+b() {
+
+_cfg=(
+    -D docs=false
+)
+
+echo "${v%.*} }"
+def
+
+}
+"#;
+
+        let pkgfile = Pkgfile::parse(contents).unwrap();
+
+        assert_eq!(pkgfile.name, "foo");
+        assert_eq!(pkgfile.version, "1.2.3");
+        assert_eq!(pkgfile.release, 2);
+        assert_eq!(pkgfile.about, "A package for testing");
+        assert_eq!(pkgfile.licenses, vec!["MIT"]);
+        assert_eq!(pkgfile.tags, vec!["lib", "test"]);
+        assert_eq!(pkgfile.sources, vec![
+            "https://github.com/toxikuu/foo/archive/v1.2.3.tar.gz -> foo-1.2.3.tgz",
+            "p,linux",
+        ]);
+        assert_eq!(pkgfile.dependencies, vec!["glibc", "b,meson"]);
+        assert!(pkgfile.kcfg.is_empty());
+        assert_eq!(pkgfile.provides, vec!["libfoo", "foo-compat"]);
+        assert_eq!(pkgfile.conflicts, vec!["bar"]);
+        assert_eq!(pkgfile.replaces, vec!["oldfoo"]);
+        assert_eq!(pkgfile.backup, vec!["etc/foo.conf"]);
+        assert!(pkgfile.version_fetch.is_empty());
+    }
+
+    #[test]
+    fn parse_user_variables() {
+        let contents = "bar@20250101-1\ntag=\"release-$v\"\nvf=no\ns=(https://example.com/${tag}.tar.xz)\n";
+        let pkgfile = Pkgfile::parse(contents).unwrap();
+
+        assert_eq!(pkgfile.release, 1);
+        assert_eq!(pkgfile.version_fetch, "no");
+        assert_eq!(pkgfile.sources, vec!["https://example.com/release-20250101.tar.xz"]);
+    }
+
+    #[test]
+    fn dynamic_expressions() {
+        for body in [
+            "s=(https://example.com/foo-${v%.*}.tar.xz)",
+            "u=$(echo hi)",
+            "a=\"$undefined\"",
+            "with rust",
+            "a=hi echo",
+        ] {
+            let contents = format!("foo@1.0-1\n\n{body}\n");
+            assert!(
+                matches!(Pkgfile::parse(&contents), Err(GenerateError::Dynamic { line: 3, .. })),
+                "'{body}' should be dynamic"
+            );
+        }
+    }
+
+    #[test]
+    fn errors_have_lines() {
+        let contents = "foo@1.0-1\na=\"ok\"\n\nd=(\n  glibc\n";
+        assert!(matches!(
+            Pkgfile::parse(contents),
+            Err(GenerateError::Syntax { line: 4, .. })
+        ));
+
+        assert!(matches!(
+            Pkgfile::parse("foo@1.0-x\n"),
+            Err(GenerateError::InvalidRelease { line: 1, .. })
+        ));
+
+        assert!(matches!(Pkgfile::parse("\na=b\n"), Err(GenerateError::MissingHeader)));
+    }
+
+    #[test]
+    fn gen_sh_output_line_count() {
+        assert!(matches!(
+            Pkgfile::from_gen_sh_output("foo\n1.0\n"),
+            Err(GenerateError::FallbackOutput(2))
+        ));
+    }
+}
@@ -0,0 +1,163 @@
+// package/provides.rs
+//! Virtual packages
+//!
+//! Packages may provide virtual names with `p=()`, for instance bash and dash both providing
+//! `sh`. Unlike aliases, a virtual name may be provided by several packages. When a dependency
+//! names a virtual package, an installed provider is used if there is one. Otherwise, the
+//! preferred provider from the config is used, or the only provider if there's just one.
+
+use std::path::Path;
+
+use tracing::{
+    debug,
+    error,
+    warn,
+};
+
+use super::{
+    FormError,
+    Package,
+    all_package_names,
+    index::Index,
+};
+use crate::CONFIG;
+
+/// # Checks whether a name is virtual, meaning no package or alias has that name, but some package
+/// provides it
+pub fn is_virtual(name: &str) -> bool {
+    if let Some(index) = Index::cached() {
+        return is_virtual_in(index, name)
+    }
+
+    !Path::new("/var/db/to/pkgs").join(name).exists() && !providers_of(name).is_empty()
+}
+
+/// # Checks whether a name is virtual in an index
+fn is_virtual_in(index: &Index, name: &str) -> bool {
+    index.resolve(name).is_none() && index.providers.get(name).is_some_and(|p| !p.is_empty())
+}
+
+/// # Finds every package that provides a name, sorted by name
+pub fn providers_of(name: &str) -> Vec<Package> {
+    if let Some(index) = Index::cached() {
+        return index
+            .providers
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter_map(|p| index.packages.get(p))
+            .cloned()
+            .collect()
+    }
+
+    all_package_names()
+        .iter()
+        .filter_map(|p| {
+            Package::from_s_file(p)
+                .inspect_err(|e| error!("Failed to form {p}: {e}"))
+                .ok()
+        })
+        .filter(|p| p.provides.iter().any(|v| v == name))
+        .collect()
+}
+
+/// # Resolves a virtual name to one of its providers
+///
+/// # Errors
+/// - `FormError::NoProvider` if nothing provides the name
+/// - `FormError::AmbiguousProvider` if several packages provide the name, none are installed, and
+///   no valid preferred provider is configured
+pub fn resolve_provider(name: &str) -> Result<Package, FormError> {
+    let providers = providers_of(name);
+    let installed = providers.iter().map(Package::is_installed).collect::<Vec<_>>();
+    let names = providers.iter().map(|p| p.name.as_str()).collect::<Vec<_>>();
+    let preferred = CONFIG.providers.get(name).map(String::as_str);
+
+    let i = choose(name, &names, &installed, preferred)?;
+    debug!("Resolved virtual package {name} to {}", names[i]);
+    Ok(providers[i].clone())
+}
+
+/// # Chooses a provider, returning its index
+///
+/// An installed provider is chosen first, favoring the preferred one if several are installed.
+/// Otherwise, the preferred provider is chosen, falling back to the sole provider.
+fn choose(
+    name: &str,
+    providers: &[&str],
+    installed: &[bool],
+    preferred: Option<&str>,
+) -> Result<usize, FormError> {
+    let position = |p: &str| providers.iter().position(|n| *n == p);
+    let preferred_idx = preferred.and_then(position);
+
+    if let Some(p) = preferred
+        && preferred_idx.is_none()
+    {
+        warn!("Preferred provider {p} for {name} doesn't provide it");
+    }
+
+    if let Some(i) = preferred_idx.filter(|i| installed[*i]) {
+        return Ok(i)
+    }
+
+    if let Some(i) = installed.iter().position(|i| *i) {
+        return Ok(i)
+    }
+
+    match (preferred_idx, providers.len()) {
+        | (Some(i), _) => Ok(i),
+        | (None, 0) => Err(FormError::NoProvider(name.to_string())),
+        | (None, 1) => Ok(0),
+        | (None, _) => {
+            error!(
+                "Multiple packages provide {name} ({}). Set `providers.{name}` in the config",
+                providers.join(", ")
+            );
+            Err(FormError::AmbiguousProvider(name.to_string()))
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn virtual_names() {
+        let mut index = Index::default();
+        index.packages.insert("bash".to_string(), Package::stub("bash", &[]));
+        index.aliases.insert("bourne".to_string(), "bash".to_string());
+        index.providers.insert("sh".to_string(), vec!["bash".to_string()]);
+
+        assert!(is_virtual_in(&index, "sh"));
+        assert!(!is_virtual_in(&index, "bash"));
+        assert!(!is_virtual_in(&index, "bourne"));
+
+        // Names nothing provides aren't virtual, just missing
+        assert!(!is_virtual_in(&index, "zsh"));
+    }
+
+    #[test]
+    fn choose_provider() {
+        let providers = ["bash", "dash"];
+
+        // Installed providers win, preferring the preferred one
+        assert_eq!(choose("sh", &providers, &[false, true], Some("bash")).unwrap(), 1);
+        assert_eq!(choose("sh", &providers, &[true, true], Some("dash")).unwrap(), 1);
+
+        // Otherwise, the preferred provider is used
+        assert_eq!(choose("sh", &providers, &[false, false], Some("dash")).unwrap(), 1);
+
+        // Without a preference, a sole provider is used, and several are ambiguous
+        assert_eq!(choose("sh", &["bash"], &[false], None).unwrap(), 0);
+        assert!(matches!(
+            choose("sh", &providers, &[false, false], None),
+            Err(FormError::AmbiguousProvider(_))
+        ));
+        assert!(matches!(
+            choose("sh", &[], &[], None),
+            Err(FormError::NoProvider(_))
+        ));
+    }
+}
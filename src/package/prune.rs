@@ -48,16 +48,16 @@ impl Package {
         };
         trace!("Found {pruneable_sources:?}");
 
+        // Versions are matched exactly, rather than with substrings, since 1.2.3-1 is a substring
+        // of 1.2.3-10. The installed version's manifest is always kept.
+        let current = self.distfile().file_name().map(|f| f.to_os_string());
+        let installed = self.installed_version().map(|iv| iv.srversion());
+
         debug!("Checking for pruneable dists");
         let pruneable_dists = if distdir.exists() {
             read_dir(distdir)?
                 .map_while(Result::ok)
-                .filter(|f| {
-                    !f.file_name()
-                        .to_string_lossy()
-                        .to_string()
-                        .contains(&version.srversion())
-                })
+                .filter(|f| Some(f.file_name()) != current)
                 .map(|f| f.path())
                 .collect::<Vec<_>>()
         } else {
@@ -71,8 +71,11 @@ impl Package {
                 .map_while(Result::ok)
                 .filter(|f| {
                     let file_name = f.file_name().to_string_lossy().to_string();
-                    // Filter for files that are manifests, but that aren't the current version
-                    !file_name.ends_with(&version.srversion()) && file_name.starts_with("MANIFEST@")
+                    // Filter for files that are manifests, but that aren't the current or
+                    // installed version
+                    file_name.strip_prefix("MANIFEST@").is_some_and(|v| {
+                        v != version.srversion() && Some(v) != installed.as_deref()
+                    })
                 })
                 .map(|f| f.path())
                 .collect::<Vec<_>>()
@@ -2,6 +2,7 @@
 //! Code related to pulling packages
 
 use std::{
+    collections::HashMap,
     fs::{
         File,
         rename,
@@ -32,6 +33,7 @@ use reqwest::{
     Client,
     Response,
     header::{
+        CONTENT_LENGTH,
         HeaderMap,
         LAST_MODIFIED,
         USER_AGENT,
@@ -137,6 +139,32 @@ pub async fn multipull(pkgs: &[Package], force: bool) -> Result<(), DownloadErro
     Ok(())
 }
 
+/// # Fetches the sizes of packages' distfiles from the server
+///
+/// Packages whose size couldn't be determined are omitted.
+pub async fn remote_sizes(pkgs: &[Package]) -> Result<HashMap<String, u64>, DownloadError> {
+    let addr = &CONFIG.server_address;
+    let client = create_client().await.map_err(DownloadError::CreateClient)?;
+
+    let tasks = pkgs.iter().map(|pkg| {
+        let client = client.clone();
+        let name = pkg.name.clone();
+        let url = pkg
+            .distfile()
+            .file_name()
+            .map(|f| format!("{addr}/{}", f.to_string_lossy()));
+
+        async move {
+            let resp = client.head(url?).send().await.ok()?.error_for_status().ok()?;
+            // NOTE: `resp.content_length()` is always 0 for head(), so the header is read instead
+            let size = resp.headers().get(CONTENT_LENGTH)?.to_str().ok()?.parse().ok()?;
+            Some((name, size))
+        }
+    });
+
+    Ok(join_all(tasks).await.into_iter().flatten().collect())
+}
+
 async fn should_download(
     client: &Client,
     url: &str,
@@ -0,0 +1,63 @@
+// package/reason.rs
+//! Install reasons
+//!
+//! Each installed package records why it's installed in `REASON` in its data directory. Packages
+//! requested by the user are explicit, while those pulled in only to satisfy dependencies are
+//! dependencies, which `to autoremove` may remove once nothing explicit needs them.
+//!
+//! Packages installed before reasons were recorded have none, and are treated as explicit.
+
+use std::{
+    fmt,
+    fs::{
+        self,
+        read_to_string,
+    },
+    io,
+};
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tracing::debug;
+
+use super::Package;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Reason {
+    Explicit,
+    Dependency,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Self::Explicit => f.pad("explicit"),
+            | Self::Dependency => f.pad("dependency"),
+        }
+    }
+}
+
+impl Package {
+    /// # Returns why a package is installed, if recorded
+    pub fn install_reason(&self) -> Option<Reason> {
+        match read_to_string(self.datadir().join("REASON")).ok()?.trim() {
+            | "explicit" => Some(Reason::Explicit),
+            | "dependency" => Some(Reason::Dependency),
+            | _ => None,
+        }
+    }
+
+    /// # Checks whether a package was installed only as a dependency
+    pub fn is_dependency_install(&self) -> bool {
+        self.install_reason() == Some(Reason::Dependency)
+    }
+
+    /// # Records why a package is installed
+    pub fn mark(&self, reason: Reason) -> io::Result<()> {
+        debug!("Marking {self:-} as {reason}");
+        fs::write(self.datadir().join("REASON"), format!("{reason}\n"))
+    }
+}
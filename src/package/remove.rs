@@ -20,6 +20,10 @@ use std::{
 };
 
 use fshelpers::rm;
+use petgraph::{
+    algo::toposort,
+    graph::DiGraph,
+};
 use thiserror::Error;
 use tracing::{
     debug,
@@ -34,12 +38,19 @@ use walkdir::{
 };
 
 use super::{
+    FormError,
     Package,
+    manifest::path_of,
     message::MessageHook,
+    owners::{
+        Owners,
+        owner_of_manifest,
+    },
+    provides::providers_of,
 };
 
 /// Paths that should never be removed, regardless what a manifest says
-const KEPT: &[&str] = &[
+pub(super) const KEPT: &[&str] = &[
     "/",
     "/bin",
     "/boot",
@@ -99,14 +110,14 @@ where
         .collect()
 }
 
-/// # Reads manifests and returns a hashmap of their paths and their contents
+/// # Reads manifests and returns a hashmap of their paths and the paths they list
 #[instrument]
-fn read_all_manifests(manifests: &[PathBuf]) -> Result<HashMap<PathBuf, Vec<String>>, io::Error> {
+pub(super) fn read_all_manifests(manifests: &[PathBuf]) -> Result<HashMap<PathBuf, Vec<String>>, io::Error> {
     let mut data = HashMap::new();
 
     for manifest in manifests {
         let contents = read_to_string(manifest)?;
-        let lines = contents.lines().map(ToString::to_string).collect();
+        let lines = contents.lines().map(|l| path_of(l).to_string()).collect();
         data.insert(manifest.clone(), lines);
     }
 
@@ -114,7 +125,7 @@ fn read_all_manifests(manifests: &[PathBuf]) -> Result<HashMap<PathBuf, Vec<Stri
 }
 
 /// # Find lines representing package install paths unique to this manifest
-/// Backend for `find_unique_paths()`
+/// Backend for `find_dead_files()`, comparing the manifests of a single package
 /// Returns the unique lines in reverse order (meaning /path/to/file is above /path/to)
 #[instrument(skip(all_data))]
 fn find_unique(
@@ -141,13 +152,19 @@ fn find_unique(
         .collect())
 }
 
-/// # Finds paths unique to a manifest
+/// # Finds paths unique to a manifest, meaning no other package in the ownership index has them
 /// Also prefixes those paths with /
-pub fn find_unique_paths(manifest: &PathBuf) -> Result<Vec<String>, io::Error> {
-    // Here a depth of 2 is used because the we are in the data directory for all packages
-    let manifests = locate("/var/db/to/data", 2);
-    let data = read_all_manifests(&manifests)?;
-    find_unique(&data, manifest)
+/// Returns the unique paths in reverse order, like `find_unique()`
+pub fn find_unique_paths(manifest: &PathBuf, owners: &Owners) -> Result<Vec<String>, io::Error> {
+    let pkg = owner_of_manifest(manifest);
+
+    Ok(read_to_string(manifest)?
+        .lines()
+        .map(path_of)
+        .filter(|p| owners.owners_of(p).is_none_or(|o| o.iter().all(|n| *n == pkg)))
+        .map(|p| format!("/{p}"))
+        .rev()
+        .collect())
 }
 
 #[derive(Debug, Error)]
@@ -161,6 +178,15 @@ pub enum RemoveError {
     #[error("Package is core")]
     Core,
 
+    #[error("Package is needed by: {0}")]
+    Dependants(String),
+
+    #[error("Failed to form package: {0}")]
+    FormError(#[from] FormError),
+
+    #[error("The {0}() hook failed")]
+    Hook(&'static str),
+
     #[error("I/O error: {0}")]
     Io(#[from] io::Error),
 }
@@ -177,27 +203,81 @@ impl Package {
         remove_critical: bool,
         suppress: bool,
     ) -> Result<(), RemoveError> {
-        if !self.is_installed() && !force {
-            warn!("Can't remove {self} as it's not installed");
-            return Err(RemoveError::NotInstalled)
-        }
+        self.remove_from(Path::new("/"), force, remove_critical, suppress)
+    }
 
+    /// # Checks whether a package may be removed, without removing it
+    ///
+    /// # Arguments
+    /// * `force`           - Whether core packages and packages with dependants may be removed
+    /// * `remove_critical` - Whether critical packages may be removed
+    /// * `incoming`        - A package about to replace this one, whose names count as provided
+    ///
+    /// # Errors
+    /// - `RemoveError::Critical` or `RemoveError::Core` if the package's tags forbid removal
+    /// - `RemoveError::Dependants` if installed packages need it
+    pub fn check_removal(
+        &self,
+        force: bool,
+        remove_critical: bool,
+        incoming: Option<&Package>,
+    ) -> Result<(), RemoveError> {
         if self.tags.iter().any(|t| t == "critical") && !remove_critical {
             warn!("Not removing {self} as it's tagged as critical");
-            warn!("To force removal, pass --im-really-fucking-stupid");
             return Err(RemoveError::Critical)
         }
 
         if self.tags.iter().any(|t| t == "core") && !force {
             warn!("Not removing {self} as it's tagged as core");
-            warn!("To force removal, pass --force");
             return Err(RemoveError::Core)
         }
 
+        if !force {
+            let dependants = self.installed_dependants_with(incoming)?;
+            if !dependants.is_empty() {
+                let names = dependants.iter().map(|p| p.name.as_str()).collect::<Vec<_>>().join(", ");
+                error!("Not removing {self:-} as installed packages need it: {names}");
+                return Err(RemoveError::Dependants(names))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// # Removes a package's files from a root
+    ///
+    /// Like when installing, hooks are skipped unless the root is /.
+    ///
+    /// # Errors
+    /// - `RemoveError::NotInstalled` if the package isn't installed and removal isn't forced
+    /// - Any error from `check_removal()`
+    /// - `RemoveError::Hook` if prer() failed
+    pub fn remove_from(
+        &self,
+        root: &Path,
+        force: bool,
+        remove_critical: bool,
+        suppress: bool,
+    ) -> Result<(), RemoveError> {
+        if !self.is_installed() && !force {
+            warn!("Can't remove {self} as it's not installed");
+            return Err(RemoveError::NotInstalled)
+        }
+
+        self.check_removal(force, remove_critical, None).inspect_err(|e| match e {
+            | RemoveError::Critical => warn!("To force removal, pass --im-really-fucking-stupid"),
+            | RemoveError::Core => warn!("To force removal, pass --force"),
+            | RemoveError::Dependants(_) => {
+                error!("To remove them too, pass --cascade. To force removal, pass --force");
+            },
+            | _ => {},
+        })?;
+
         // TODO: Use `ManifestError::MissingManifest`
         let manifest = self.manifest().ok_or(RemoveError::NotInstalled)?;
+        let hooks = root == Path::new("/");
 
-        let unique = match find_unique_paths(&manifest) {
+        let unique = match Owners::for_root(root).and_then(|o| find_unique_paths(&manifest, &o)) {
             | Ok(u) => u,
             | Err(e) => {
                 error!(
@@ -207,14 +287,18 @@ impl Package {
             },
         };
 
-        // TODO: Add support for prer() here
-        //       This serves as a pre-remove hook in the pkgfile
+        if hooks {
+            self.run_hook("prer", &[]).map_err(|e| {
+                error!("Not removing {self:-} as prer() failed: {e}");
+                RemoveError::Hook("prer")
+            })?;
+        }
 
-        trace!("Removing paths unique to {self}: {unique:#?}");
+        trace!("Removing paths unique to {self} from {}: {unique:#?}", root.display());
         unique.iter().for_each(|p| {
-            let path = Path::new("/").join(p);
+            let path = root.join(p.trim_start_matches('/'));
 
-            if KEPT.iter().any(|&s| path.ends_with(s)) {
+            if KEPT.iter().any(|&s| Path::new(p).ends_with(s)) {
                 debug!("Retaining protected path: '{}'", path.display());
                 return;
             }
@@ -226,11 +310,21 @@ impl Package {
             trace!("'{p}' -x");
         });
 
-        // TODO: Add support for r() here
-        //       This serves as a post-remove hook in the pkgfile
+        // The files are already gone, so a failing postr() can't stop the removal
+        if hooks
+            && let Err(e) = self.run_hook("postr", &[])
+        {
+            warn!("postr() failed for {self:-}: {e}");
+        }
 
         // This should not fail
+        let old = self.installed_version();
+        let reason = self.install_reason();
         rm(self.datadir().join("IV"))?;
+        rm(self.datadir().join("REASON"))?;
+        rm(self.datadir().join("SIZE"))?;
+        Owners::update(|o| o.forget(&self.name));
+        self.log_removing(old, reason);
 
         // TODO: Add flags and configure options for removing dists and sources
 
@@ -238,18 +332,43 @@ impl Package {
         Ok(())
     }
 
-    // FIX: Finds newly installed files instead of actual dead files :sob:
-    // TODO: Maybe fixed? ^
-    #[instrument(skip(self))]
-    pub fn remove_dead_files_after_update(&self) -> Result<(), RemoveError> {
-        if !self.is_installed() {
-            warn!(
-                "Attempted to update '{self:-}' despite it not being installed. Kindly report this as a bug."
-            );
-            return Err(RemoveError::NotInstalled)
-        }
+    /// # Finds installed packages that would break if this package were removed
+    ///
+    /// These are installed packages with a required or runtime dependency on this package, or on
+    /// a name it provides, that no other installed package provides.
+    pub fn installed_dependants(&self) -> Result<Vec<Package>, FormError> {
+        self.installed_dependants_with(None)
+    }
+
+    /// # Finds installed packages that would break if this package were replaced by another
+    ///
+    /// Names the incoming package provides count as provided, even before it's installed.
+    fn installed_dependants_with(
+        &self,
+        incoming: Option<&Package>,
+    ) -> Result<Vec<Package>, FormError> {
+        let other_provider = |name: &str| {
+            incoming.is_some_and(|i| i.name == name || i.provides.iter().any(|p| p == name))
+                || providers_of(name)
+                    .iter()
+                    .any(|p| p.name != self.name && p.is_installed())
+        };
+
+        Ok(self
+            .dependants()?
+            .into_iter()
+            .filter(|p| p.name != self.name && incoming.is_none_or(|i| p.name != i.name))
+            .filter(Package::is_installed)
+            .filter(|p| breaks(p, self, other_provider))
+            .collect())
+    }
 
-        let dead_files = find_dead_files(self)?;
+    /// # Removes files the previous version installed that the new one doesn't
+    ///
+    /// The dead files are found with `find_dead_files()` before the new IV is written, since
+    /// they're found through the old one.
+    #[instrument(skip(self, dead_files))]
+    pub fn remove_dead_files_after_update(&self, dead_files: &[String]) {
         debug!("Found dead files for {self:-}:\n{dead_files:#?}");
         dead_files.iter().for_each(|p| {
             let path = Path::new(p);
@@ -267,9 +386,92 @@ impl Package {
 
             trace!("'{p}' -x");
         });
+    }
+}
 
-        Ok(())
+/// # Orders packages for removal, with dependants before their dependencies
+///
+/// With `cascade`, installed dependants of the packages are added transitively, so removing the
+/// returned packages in order never breaks an installed package.
+///
+/// # Errors
+/// - Will fail if any dependant could not be formed
+pub fn plan_removal(pkgs: Vec<Package>, cascade: bool) -> Result<Vec<Package>, FormError> {
+    plan_removal_with(pkgs, cascade, Package::installed_dependants)
+}
+
+/// # Orders packages for removal, finding installed dependants with `dependants_of`
+fn plan_removal_with(
+    pkgs: Vec<Package>,
+    cascade: bool,
+    dependants_of: impl Fn(&Package) -> Result<Vec<Package>, FormError>,
+) -> Result<Vec<Package>, FormError> {
+    let mut all = pkgs;
+
+    if cascade {
+        let mut i = 0;
+        while i < all.len() {
+            for dependant in dependants_of(&all[i])? {
+                if !all.iter().any(|p| p.name == dependant.name) {
+                    debug!("Cascading removal of {} to {dependant:-}", all[i].name);
+                    all.push(dependant);
+                }
+            }
+            i += 1;
+        }
     }
+
+    let mut graph = DiGraph::<Package, ()>::new();
+    let indices = all
+        .into_iter()
+        .map(|p| (p.name.clone(), graph.add_node(p)))
+        .collect::<HashMap<_, _>>();
+
+    for &idx in indices.values() {
+        for dependant in dependants_of(&graph[idx])? {
+            if let Some(&from) = indices.get(&dependant.name) {
+                graph.add_edge(from, idx, ());
+            }
+        }
+    }
+
+    match toposort(&graph, None) {
+        | Ok(sorted) => Ok(sorted.into_iter().map(|idx| graph[idx].clone()).collect()),
+        | Err(e) => {
+            warn!("Dependency cycle involving {}; removal order may be wrong", graph[e.node_id()].name);
+            Ok(graph.node_weights().cloned().collect())
+        },
+    }
+}
+
+/// # Finds the installed dependants of a package that a removal plan leaves in place
+///
+/// Removing the package would break them, so it's refused unless forced.
+///
+/// # Errors
+/// - Will fail if any dependant could not be formed
+pub fn unplanned_dependants(pkg: &Package, plan: &[Package]) -> Result<Vec<String>, FormError> {
+    Ok(unplanned(pkg.installed_dependants()?, plan))
+}
+
+fn unplanned(dependants: Vec<Package>, plan: &[Package]) -> Vec<String> {
+    dependants
+        .into_iter()
+        .filter(|d| !plan.iter().any(|p| p.name == d.name))
+        .map(|d| d.name)
+        .collect()
+}
+
+/// # Checks whether a dependant needs a removed package
+///
+/// It does if a required or runtime dependency names the package, or a name it provides, and
+/// nothing else provides that name.
+fn breaks(dependant: &Package, removed: &Package, other_provider: impl Fn(&str) -> bool) -> bool {
+    dependant.dependencies.iter().any(|d| {
+        d.kind.for_install()
+            && (d.name == removed.name || removed.provides.contains(&d.name))
+            && !other_provider(&d.name)
+    })
 }
 
 /// # Finds unique (dead) files in an old manifest
@@ -305,3 +507,74 @@ pub fn find_dead_files(package: &Package) -> Result<Vec<String>, RemoveError> {
 
     Ok(find_unique(&data, &old_manifest)?)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// `tool` depends on `app`, which depends on `lib`
+    fn dependants_of(pkg: &Package) -> Result<Vec<Package>, FormError> {
+        let names: &[&str] = match pkg.name.as_str() {
+            | "lib" => &["app"],
+            | "app" => &["tool"],
+            | _ => &[],
+        };
+        Ok(names.iter().map(|n| Package::stub(n, &[])).collect())
+    }
+
+    fn names(pkgs: &[Package]) -> Vec<&str> { pkgs.iter().map(|p| p.name.as_str()).collect() }
+
+    #[test]
+    fn cascade_order() {
+        let plan = plan_removal_with(vec![Package::stub("lib", &[])], true, dependants_of).unwrap();
+        assert_eq!(names(&plan), ["tool", "app", "lib"]);
+
+        // Requested packages are ordered too
+        let pkgs = vec![Package::stub("lib", &[]), Package::stub("tool", &[])];
+        let plan = plan_removal_with(pkgs, false, dependants_of).unwrap();
+        assert_eq!(names(&plan), ["tool", "lib"]);
+    }
+
+    #[test]
+    fn refuse_without_cascade() {
+        let plan =
+            plan_removal_with(vec![Package::stub("lib", &[])], false, dependants_of).unwrap();
+        assert_eq!(names(&plan), ["lib"]);
+        assert_eq!(unplanned(dependants_of(&plan[0]).unwrap(), &plan), ["app"]);
+
+        let plan = plan_removal_with(vec![Package::stub("lib", &[])], true, dependants_of).unwrap();
+        assert!(
+            plan.iter()
+                .all(|p| unplanned(dependants_of(p).unwrap(), &plan).is_empty())
+        );
+    }
+
+    #[test]
+    fn replaced_dependants() {
+        use crate::package::dep::DepKind;
+
+        let mut old = Package::stub("old", &[]);
+        old.provides = vec!["virt".to_string()];
+        let app = Package::stub("app", &[("old", DepKind::Required)]);
+        let docs = Package::stub("docs", &[("virt", DepKind::Optional)]);
+
+        assert!(breaks(&app, &old, |_| false));
+        assert!(!breaks(&docs, &old, |_| false));
+
+        // A replacement that provides the removed package's name satisfies its dependants
+        assert!(!breaks(&app, &old, |n| n == "old"));
+    }
+
+    #[test]
+    fn cycle_fallback() {
+        let dependants_of = |pkg: &Package| {
+            let name = if pkg.name == "a" { "b" } else { "a" };
+            Ok(vec![Package::stub(name, &[])])
+        };
+
+        let plan = plan_removal_with(vec![Package::stub("a", &[])], true, dependants_of).unwrap();
+        let mut names = names(&plan);
+        names.sort_unstable();
+        assert_eq!(names, ["a", "b"]);
+    }
+}
@@ -0,0 +1,473 @@
+// package/sandbox.rs
+//! Build backends
+//!
+//! Builds run `/runner` inside an overlay over the stagefile. The chroot backend mounts the overlay
+//! on the host and enters it with `chroot`, so it needs real root. The namespace backend instead
+//! clones the runner into new user, mount, PID and UTS namespaces, where it's root, and mounts the
+//! overlay there with unprivileged overlayfs. Those mounts never appear on the host, and vanish
+//! when the runner exits.
+//!
+//! Only the caller's uid and gid are mapped into the namespace, so builds that chown files to
+//! other users fail. Since builds as root keep every owner, the chroot backend is preferred for
+//! root.
+
+use std::{
+    ffi::{
+        CString,
+        c_char,
+    },
+    fmt,
+    fs::{
+        self,
+        File,
+        read_to_string,
+    },
+    io,
+    os::{
+        fd::{
+            AsRawFd,
+            OwnedFd,
+            RawFd,
+        },
+        unix::ffi::OsStrExt,
+    },
+    path::Path,
+    ptr,
+};
+
+use nix::{
+    errno::Errno,
+    fcntl::OFlag,
+    libc,
+    mount::{
+        MsFlags,
+        mount,
+    },
+    sched::{
+        CloneFlags,
+        clone,
+    },
+    sys::{
+        signal::{
+            Signal,
+            kill,
+        },
+        wait::{
+            WaitStatus,
+            waitpid,
+        },
+    },
+    unistd::{
+        Pid,
+        chdir,
+        chroot,
+        getegid,
+        geteuid,
+        pipe2,
+        read,
+        sethostname,
+    },
+};
+use serde::Deserialize;
+use tracing::{
+    debug,
+    error,
+    warn,
+};
+
+use crate::utils::exec::log_lines;
+
+/// The stack given to the cloned child, which only runs until it executes the runner
+const STACK_SIZE: usize = 1024 * 1024;
+
+/// # A build backend
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Chroot as root, or namespaces otherwise
+    #[default]
+    Auto,
+    /// User namespaces, which don't need root
+    Namespace,
+    /// Host mounts and chroot, which need root
+    Chroot,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Self::Auto => f.pad("auto"),
+            | Self::Namespace => f.pad("namespace"),
+            | Self::Chroot => f.pad("chroot"),
+        }
+    }
+}
+
+impl Backend {
+    /// # Resolves `Auto` to a concrete backend
+    ///
+    /// Root uses the chroot backend. Other users use the namespace backend, falling back to the
+    /// chroot backend if unprivileged user namespaces are disabled.
+    pub fn resolve(self) -> Self {
+        match self {
+            | Self::Auto if is_root() => Self::Chroot,
+            | Self::Auto if !userns_enabled() => {
+                warn!("Unprivileged user namespaces are disabled, falling back to chroot");
+                Self::Chroot
+            },
+            | Self::Auto => Self::Namespace,
+            | b => b,
+        }
+    }
+}
+
+/// # Checks whether the current user is root
+pub fn is_root() -> bool { geteuid().is_root() }
+
+/// # Checks whether the kernel allows unprivileged user namespaces
+fn userns_enabled() -> bool {
+    let disabled = |path: &str| read_to_string(path).is_ok_and(|v| v.trim() == "0");
+    !disabled("/proc/sys/kernel/unprivileged_userns_clone")
+        && !disabled("/proc/sys/user/max_user_namespaces")
+}
+
+/// # The step of namespace setup that failed in the child, reported through a pipe
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+enum Step {
+    Sync,
+    Output,
+    Propagation,
+    Hostname,
+    Overlay,
+    Dev,
+    Devpts,
+    Proc,
+    Sys,
+    Run,
+    Chroot,
+    Exec,
+}
+
+impl Step {
+    const ALL: [Self; 12] = [
+        Self::Sync,
+        Self::Output,
+        Self::Propagation,
+        Self::Hostname,
+        Self::Overlay,
+        Self::Dev,
+        Self::Devpts,
+        Self::Proc,
+        Self::Sys,
+        Self::Run,
+        Self::Chroot,
+        Self::Exec,
+    ];
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Self::Sync => f.pad("waiting for id maps"),
+            | Self::Output => f.pad("redirecting output"),
+            | Self::Propagation => f.pad("making mounts private"),
+            | Self::Hostname => f.pad("setting the hostname"),
+            | Self::Overlay => f.pad("mounting the overlay"),
+            | Self::Dev => f.pad("binding /dev"),
+            | Self::Devpts => f.pad("mounting /dev/pts"),
+            | Self::Proc => f.pad("mounting /proc"),
+            | Self::Sys => f.pad("binding /sys"),
+            | Self::Run => f.pad("mounting /run"),
+            | Self::Chroot => f.pad("entering the overlay"),
+            | Self::Exec => f.pad("executing the runner"),
+        }
+    }
+}
+
+/// # Everything the child needs, prepared before cloning
+///
+/// The child may not allocate, since another thread could hold the allocator's lock when the
+/// process is cloned. The pointer arrays point into `_args` and `_env`, which own them.
+struct Prepared {
+    overlay: CString,
+    merged:  CString,
+    dev:     CString,
+    pts:     CString,
+    proc:    CString,
+    sys:     CString,
+    run:     CString,
+    _args:   Vec<CString>,
+    _env:    Vec<CString>,
+    argv:    Vec<*const c_char>,
+    envp:    Vec<*const c_char>,
+}
+
+fn cstring(bytes: impl Into<Vec<u8>>) -> io::Result<CString> {
+    CString::new(bytes).map_err(io::Error::other)
+}
+
+fn pointers(strings: &[CString]) -> Vec<*const c_char> {
+    strings
+        .iter()
+        .map(|s| s.as_ptr())
+        .chain([ptr::null()])
+        .collect()
+}
+
+impl Prepared {
+    fn new(chroot: &Path, env: &[(&str, String)], command: &str) -> io::Result<Self> {
+        let path = |p: &str| cstring(chroot.join(p).as_os_str().as_bytes());
+        let overlay = format!(
+            "lowerdir={},upperdir={},workdir={},userxattr",
+            chroot.join("lower").display(),
+            chroot.join("upper").display(),
+            chroot.join("work").display(),
+        );
+
+        let args = vec![cstring(command)?];
+        let env = env
+            .iter()
+            .map(|(k, v)| cstring(format!("{k}={v}")))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            overlay: cstring(overlay)?,
+            merged:  path("merged")?,
+            dev:     path("merged/dev")?,
+            pts:     path("merged/dev/pts")?,
+            proc:    path("merged/proc")?,
+            sys:     path("merged/sys")?,
+            run:     path("merged/run")?,
+            argv:    pointers(&args),
+            envp:    pointers(&env),
+            _args:   args,
+            _env:    env,
+        })
+    }
+}
+
+/// # The child's copies of the pipes it shares with the parent
+#[derive(Debug, Clone, Copy)]
+struct Fds {
+    sync:        RawFd,
+    sync_parent: RawFd,
+    report:      RawFd,
+    stdout:      RawFd,
+    stderr:      RawFd,
+}
+
+/// # Sets up the namespaces and executes the command, in the cloned child
+///
+/// The child blocks until the parent closes its end of `sync`, after writing the id maps. Setup
+/// failures are written to `report` as the failed step and errno.
+fn child(p: &Prepared, fds: Fds) -> isize {
+    let fail = |step: Step, errno: Errno| {
+        let errno = (errno as i32).to_ne_bytes();
+        let msg = [step as u8, errno[0], errno[1], errno[2], errno[3]];
+        // SAFETY: `report` is open in the child until it exits or executes
+        let _ = unsafe { libc::write(fds.report, msg.as_ptr().cast(), msg.len()) };
+        127
+    };
+
+    // SAFETY: The child owns its copies of the pipe's file descriptors
+    unsafe { libc::close(fds.sync_parent) };
+    let mut byte = [0];
+    // SAFETY: `sync` is open in the child
+    if unsafe { libc::read(fds.sync, byte.as_mut_ptr().cast(), 1) } != 0 {
+        return fail(Step::Sync, Errno::last())
+    }
+
+    // SAFETY: The output pipes are open in the child, and the duplicates aren't close-on-exec
+    if unsafe { libc::dup2(fds.stdout, 1) } < 0 || unsafe { libc::dup2(fds.stderr, 2) } < 0 {
+        return fail(Step::Output, Errno::last())
+    }
+
+    let none = None::<&str>;
+    let bind = MsFlags::MS_BIND | MsFlags::MS_REC;
+    let at = |step: Step| move |e: Errno| (step, e);
+    let setup = || -> Result<(), (Step, Errno)> {
+        mount(none, "/", none, MsFlags::MS_REC | MsFlags::MS_PRIVATE, none)
+            .map_err(at(Step::Propagation))?;
+        sethostname("to-build").map_err(at(Step::Hostname))?;
+        mount(
+            Some("overlay"),
+            p.merged.as_c_str(),
+            Some("overlay"),
+            MsFlags::empty(),
+            Some(p.overlay.as_c_str()),
+        )
+        .map_err(at(Step::Overlay))?;
+
+        mount(Some("/dev"), p.dev.as_c_str(), none, bind, none).map_err(at(Step::Dev))?;
+        mount(
+            Some("devpts"),
+            p.pts.as_c_str(),
+            Some("devpts"),
+            MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC,
+            Some("newinstance,ptmxmode=0666,mode=0620"),
+        )
+        .map_err(at(Step::Devpts))?;
+        mount(
+            Some("proc"),
+            p.proc.as_c_str(),
+            Some("proc"),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+            none,
+        )
+        .map_err(at(Step::Proc))?;
+
+        // Mounting a fresh sysfs needs a network namespace, which would cut builds off from the
+        // network
+        mount(Some("/sys"), p.sys.as_c_str(), none, bind, none).map_err(at(Step::Sys))?;
+        mount(
+            Some("tmpfs"),
+            p.run.as_c_str(),
+            Some("tmpfs"),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            none,
+        )
+        .map_err(at(Step::Run))?;
+
+        chroot(p.merged.as_c_str()).map_err(at(Step::Chroot))?;
+        chdir("/").map_err(at(Step::Chroot))
+    };
+
+    if let Err((step, e)) = setup() {
+        return fail(step, e)
+    }
+
+    // SAFETY: `argv` and `envp` are null-terminated arrays of pointers into `Prepared`
+    unsafe { libc::execve(p.argv[0], p.argv.as_ptr(), p.envp.as_ptr()) };
+    fail(Step::Exec, Errno::last())
+}
+
+/// # Writes the child's id maps, mapping root inside the namespace to the caller
+fn write_id_maps(pid: Pid) -> io::Result<()> {
+    let proc = Path::new("/proc").join(pid.to_string());
+    fs::write(proc.join("uid_map"), format!("0 {} 1\n", geteuid()))?;
+    fs::write(proc.join("setgroups"), "deny\n")?;
+    fs::write(proc.join("gid_map"), format!("0 {} 1\n", getegid()))
+}
+
+/// # Reads a setup failure reported by the child, if any
+fn read_report(report: &OwnedFd) -> Option<(Step, Errno)> {
+    let mut msg = [0; 5];
+    let mut len = 0;
+    while len < msg.len() {
+        match read(report, &mut msg[len..]) {
+            | Ok(0) => break,
+            | Ok(n) => len += n,
+            | Err(Errno::EINTR) => continue,
+            | Err(_) => break,
+        }
+    }
+
+    if len < msg.len() {
+        return None
+    }
+
+    let step = *Step::ALL.get(msg[0] as usize)?;
+    let errno = Errno::from_raw(i32::from_ne_bytes([msg[1], msg[2], msg[3], msg[4]]));
+    Some((step, errno))
+}
+
+/// # Runs a command inside the build overlay in new namespaces
+///
+/// The overlay is made from `lower`, `upper`, `work` and `merged` under `chroot`. The command runs
+/// as root in the namespaces, with only `env` in its environment. Its output is logged like
+/// `exec!()`'s.
+///
+/// The child is cloned rather than forked after `unshare()`, since a multithreaded process can't
+/// unshare its user namespace, and the first process in a PID namespace must be its init.
+///
+/// # Errors
+/// - The namespaces couldn't be created, or the id maps couldn't be written
+/// - Setup failed in the child
+///
+/// Returns the command's exit code.
+pub fn run_in_namespace(chroot: &Path, env: &[(&str, String)], command: &str) -> io::Result<i32> {
+    let prepared = Prepared::new(chroot, env, command)?;
+    let (sync, sync_parent) = pipe2(OFlag::O_CLOEXEC)?;
+    let (report, report_child) = pipe2(OFlag::O_CLOEXEC)?;
+    let (stdout, stdout_child) = pipe2(OFlag::O_CLOEXEC)?;
+    let (stderr, stderr_child) = pipe2(OFlag::O_CLOEXEC)?;
+    let fds = Fds {
+        sync:        sync.as_raw_fd(),
+        sync_parent: sync_parent.as_raw_fd(),
+        report:      report_child.as_raw_fd(),
+        stdout:      stdout_child.as_raw_fd(),
+        stderr:      stderr_child.as_raw_fd(),
+    };
+
+    let flags = CloneFlags::CLONE_NEWUSER
+        | CloneFlags::CLONE_NEWNS
+        | CloneFlags::CLONE_NEWPID
+        | CloneFlags::CLONE_NEWUTS;
+    let mut stack = vec![0; STACK_SIZE];
+
+    // SAFETY: The child doesn't allocate or touch locks before executing or exiting
+    let pid = unsafe {
+        clone(
+            Box::new(|| child(&prepared, fds)),
+            &mut stack,
+            flags,
+            Some(libc::SIGCHLD),
+        )
+    }
+    .map_err(|e| {
+        error!("Failed to create build namespaces: {e}");
+        io::Error::from(e)
+    })?;
+    debug!("Cloned build namespace init {pid}");
+
+    drop((sync, report_child, stdout_child, stderr_child));
+    let stdout_thread = log_lines(File::from(stdout), false);
+    let stderr_thread = log_lines(File::from(stderr), true);
+
+    if let Err(e) = write_id_maps(pid) {
+        error!("Failed to write id maps for {pid}: {e}");
+        let _ = kill(pid, Signal::SIGKILL);
+        let _ = waitpid(pid, None);
+        return Err(e)
+    }
+
+    // Closing our end of the pipe lets the child continue
+    drop(sync_parent);
+    let failure = read_report(&report);
+
+    let status = loop {
+        match waitpid(pid, None) {
+            | Err(Errno::EINTR) => continue,
+            | result => break result?,
+        }
+    };
+
+    let _ = stdout_thread.join();
+    let _ = stderr_thread.join();
+
+    if let Some((step, errno)) = failure {
+        error!("Build namespace setup failed while {step}: {errno}");
+        return Err(io::Error::from(errno))
+    }
+
+    match status {
+        | WaitStatus::Exited(_, code) => Ok(code),
+        | WaitStatus::Signaled(_, signal, _) => {
+            warn!("Build was killed by {signal}");
+            Ok(128 + signal as i32)
+        },
+        | s => Err(io::Error::other(format!("Unexpected wait status: {s:?}"))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reported_steps() {
+        // Steps are reported by discriminant, so `ALL` must be in declaration order
+        assert!(Step::ALL.iter().enumerate().all(|(i, s)| *s as usize == i));
+    }
+}
@@ -0,0 +1,195 @@
+// package/size.rs
+//! Package sizes
+//!
+//! Builds record the installed size and file count of a package in `SIZE` at the root of its
+//! distfile, as `key=value` lines. Installing copies it into the data directory alongside the
+//! distfile's size, which can't be recorded in the distfile itself.
+//!
+//! Distfiles built before sizes were recorded fall back to summing their manifest records.
+
+use std::{
+    fmt,
+    fs::{
+        self,
+        read_to_string,
+    },
+    io,
+    path::Path,
+};
+
+use fshelpers::rm;
+use indicatif::HumanBytes;
+use tracing::{
+    debug,
+    warn,
+};
+
+use super::{
+    Package,
+    manifest::Entry,
+};
+use crate::sex;
+
+/// # The recorded sizes of a package
+///
+/// # Fields
+/// * `installed`       - The total size of the package's files, in bytes
+/// * `files`           - The number of files and symlinks the package installs
+/// * `distfile`        - The size of the package's distfile, in bytes, if known
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Sizes {
+    pub installed: u64,
+    pub files:     u64,
+    pub distfile:  Option<u64>,
+}
+
+impl Sizes {
+    /// # Parses the contents of a `SIZE` file
+    ///
+    /// Unknown keys are ignored. Returns `None` if the installed size or file count is missing.
+    pub fn parse(contents: &str) -> Option<Self> {
+        let (mut installed, mut files, mut distfile) = (None, None, None);
+        for (key, value) in contents.lines().filter_map(|l| l.split_once('=')) {
+            let value = value.trim().parse().ok();
+            match key.trim() {
+                | "installed" => installed = value,
+                | "files" => files = value,
+                | "distfile" => distfile = value,
+                | _ => {},
+            }
+        }
+
+        Some(Self {
+            installed: installed?,
+            files: files?,
+            distfile,
+        })
+    }
+
+    /// # Sums the sizes from manifest entries
+    ///
+    /// Directories aren't counted. Returns `None` if any entry is a plain path, since its size
+    /// isn't recorded.
+    pub fn from_entries(entries: &[Entry]) -> Option<Self> {
+        let mut sizes = Self::default();
+        for entry in entries {
+            let record = entry.record.as_ref()?;
+            if record.kind != 'd' {
+                sizes.installed += record.size;
+                sizes.files += 1;
+            }
+        }
+        Some(sizes)
+    }
+}
+
+impl fmt::Display for Sizes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "installed={}", self.installed)?;
+        writeln!(f, "files={}", self.files)?;
+        if let Some(distfile) = self.distfile {
+            writeln!(f, "distfile={distfile}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Package {
+    /// # Returns the recorded sizes of an installed package
+    ///
+    /// Falls back to the installed manifest if no sizes were recorded.
+    pub fn installed_sizes(&self) -> Option<Sizes> {
+        read_to_string(self.datadir().join("SIZE"))
+            .ok()
+            .and_then(|c| Sizes::parse(&c))
+            .or_else(|| Sizes::from_entries(&self.manifest_entries().ok()?))
+    }
+
+    /// # Returns the sizes of a package's distfile
+    ///
+    /// Falls back to the distfile's MANIFEST if it doesn't record sizes.
+    pub fn distfile_sizes(&self) -> Option<Sizes> {
+        let distfile = self.distfile();
+        let len = distfile.metadata().ok()?.len();
+        let sizes = sex!("tar xOf '{}' SIZE 2>/dev/null", distfile.display())
+            .ok()
+            .and_then(|c| Sizes::parse(&c))
+            .or_else(|| {
+                let manifest = sex!("tar xOf '{}' MANIFEST", distfile.display()).ok()?;
+                Sizes::from_entries(&manifest.lines().map(Entry::parse).collect::<Vec<_>>())
+            })?;
+
+        Some(Sizes { distfile: Some(len), ..sizes })
+    }
+
+    /// # Records a package's sizes in its data directory
+    ///
+    /// Sizes are read from `staged`, the extracted distfile's `SIZE`, falling back to its
+    /// MANIFEST. If neither records them, any previously recorded sizes are removed.
+    ///
+    /// # Errors
+    /// - The sizes couldn't be written
+    pub fn record_sizes(&self, staged: &Path) -> io::Result<()> {
+        let path = self.datadir().join("SIZE");
+        let sizes = read_to_string(staged.join("SIZE"))
+            .ok()
+            .and_then(|c| Sizes::parse(&c))
+            .or_else(|| {
+                let manifest = read_to_string(staged.join("MANIFEST")).ok()?;
+                Sizes::from_entries(&manifest.lines().map(Entry::parse).collect::<Vec<_>>())
+            });
+
+        let Some(sizes) = sizes else {
+            warn!("Sizes aren't recorded for {self:-}");
+            return rm(path)
+        };
+
+        let sizes = Sizes {
+            distfile: self.distfile().metadata().ok().map(|m| m.len()),
+            ..sizes
+        };
+        debug!(
+            "Recording sizes for {self:-}: {}",
+            HumanBytes(sizes.installed)
+        );
+        fs::write(path, sizes.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_sizes() {
+        let sizes = Sizes::parse("installed=2048\nfiles=3\n").unwrap();
+        assert_eq!(sizes, Sizes {
+            installed: 2048,
+            files:     3,
+            distfile:  None,
+        });
+
+        let sizes = Sizes { distfile: Some(512), ..sizes };
+        assert_eq!(Sizes::parse(&sizes.to_string()), Some(sizes));
+        assert_eq!(Sizes::parse("files=3\n"), None);
+    }
+
+    #[test]
+    fn sizes_from_entries() {
+        let entries = [
+            "usr\td\t0755\t0:0\t4096\t-\t-",
+            "usr/bin/foo\tf\t0755\t0:0\t100\tabc\t-",
+            "usr/bin/bar\tl\t0777\t0:0\t3\t-\tfoo",
+        ]
+        .map(Entry::parse);
+        assert_eq!(
+            Sizes::from_entries(&entries),
+            Some(Sizes {
+                installed: 103,
+                files:     2,
+                distfile:  None,
+            })
+        );
+        assert_eq!(Sizes::from_entries(&[Entry::parse("usr/bin/foo")]), None);
+    }
+}
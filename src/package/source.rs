@@ -28,15 +28,11 @@ use super::{
 };
 use crate::{
     exec,
-    utils::{
-        file::is_download,
-        parse::us_array,
-    },
+    utils::file::is_download,
 };
 
-pub fn parse_sources(raw: &str) -> Vec<Source> {
-    us_array(raw)
-        .iter()
+pub fn parse_sources(raw: &[String]) -> Vec<Source> {
+    raw.iter()
         .map(|s| Source::from_string(s))
         .collect()
 }
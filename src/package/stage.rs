@@ -0,0 +1,391 @@
+// package/stage.rs
+//! Staged installation
+//!
+//! A package's distfile is extracted into a staging directory under the root, so it's usually on the
+//! same filesystem, and its entries are then renamed into place one by one. Entries bound for
+//! another mount, like a separate /boot, are copied next to their target and renamed from there.
+//!
+//! Files that get overwritten are kept as backups, and the package's data files (IV, REASON, SIZE,
+//! and the manifest) are snapshotted, until the stage is committed. Rolling back undoes every rename and
+//! restores the snapshot.
+
+use std::{
+    fs::{
+        self,
+        rename,
+    },
+    io,
+    os::unix::fs::{
+        MetadataExt,
+        lchown,
+        symlink,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use tempfile::{
+    Builder,
+    TempDir,
+};
+use tracing::{
+    debug,
+    error,
+    trace,
+    warn,
+};
+use walkdir::WalkDir;
+
+use super::{
+    Package,
+    backup::with_suffix,
+};
+use crate::exec;
+
+/// The suffix for files overwritten by a stage, until it's committed
+const BACKUP_SUFFIX: &str = ".to-backup";
+/// The suffix for files copied next to their target from another filesystem
+const COPY_SUFFIX: &str = ".to-new";
+
+/// # A change a stage made to the root
+#[derive(Debug)]
+enum Change {
+    /// A path that didn't exist before
+    Created(PathBuf),
+    /// A file that was moved to a backup and replaced
+    Replaced { target: PathBuf, backup: PathBuf },
+}
+
+/// # A package's distfile, extracted and waiting to be applied to the root
+///
+/// Created by `Package::stage()`, applied with `apply()`, and consumed by either `commit()` or
+/// `rollback()`.
+#[must_use]
+#[derive(Debug)]
+pub struct Stage {
+    dir:      TempDir,
+    root:     PathBuf,
+    changes:  Vec<Change>,
+    snapshot: Vec<(PathBuf, Option<Vec<u8>>)>,
+}
+
+impl Package {
+    /// # Extracts a package's distfile into a staging directory under `root`
+    ///
+    /// The package's data files are snapshotted so they can be restored on rollback.
+    ///
+    /// # Errors
+    /// - The staging directory couldn't be created
+    /// - Extraction failed
+    pub fn stage(&self, root: &Path) -> io::Result<Stage> {
+        let dir = Builder::new().prefix(".to-stage-").tempdir_in(root)?;
+        debug!("Staging {self:-} in {}", dir.path().display());
+
+        exec!(
+            r#"
+
+        set -euo pipefail
+
+        tar xf '{dist}' -C {dir:?} \
+            --numeric-owner
+
+        "#,
+            dist = self.distfile().display(),
+            dir = dir.path(),
+        )?;
+
+        let data = self.datadir();
+        let mut snapshot = vec![data.join("IV"), data.join("REASON")];
+        snapshot.extend(self.manifest());
+        snapshot.push(data.join(format!("MANIFEST@{}", self.version.srversion())));
+        snapshot.push(data.join("SIZE"));
+
+        Ok(Stage {
+            dir,
+            root: root.to_path_buf(),
+            changes: Vec::new(),
+            snapshot: snapshot.into_iter().map(|p| (p.clone(), fs::read(p).ok())).collect(),
+        })
+    }
+}
+
+impl Stage {
+    /// # Returns the path of the staging directory
+    pub fn path(&self) -> &Path { self.dir.path() }
+
+    /// # Returns the path of the staged MANIFEST
+    pub fn manifest(&self) -> PathBuf { self.dir.path().join("MANIFEST") }
+
+    /// # Renames every staged entry into place under the root
+    ///
+    /// Existing directories, including symlinks to directories, are kept and descended into.
+    /// Existing files are moved to backups. On error, the caller should `rollback()`.
+    ///
+    /// # Errors
+    /// - A staged entry would replace a directory with a file, or a file with a directory
+    /// - A rename failed
+    pub fn apply(&mut self) -> io::Result<()> {
+        let mut entries = WalkDir::new(self.dir.path())
+            .min_depth(1)
+            .sort_by_file_name()
+            .into_iter();
+
+        while let Some(entry) = entries.next() {
+            let entry = entry?;
+            let rel = entry.path().strip_prefix(self.dir.path()).map_err(io::Error::other)?;
+            if rel == Path::new("MANIFEST") || rel == Path::new("SIZE") {
+                continue
+            }
+
+            let target = self.root.join(rel);
+            let existing = target.symlink_metadata().ok();
+
+            if entry.file_type().is_dir() {
+                match existing {
+                    | None => {
+                        match rename(entry.path(), &target) {
+                            | Ok(()) => entries.skip_current_dir(),
+                            // Across filesystems, the directory is recreated and its entries are
+                            // moved one by one
+                            | Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                                create_dir_like(entry.path(), &target)?
+                            },
+                            | Err(e) => return Err(e),
+                        }
+                        trace!("'{}' +d", target.display());
+                        self.changes.push(Change::Created(target));
+                    },
+                    | Some(_) if target.is_dir() => {},
+                    | Some(_) => {
+                        return Err(io::Error::other(format!(
+                            "Refusing to replace non-directory '{}' with a directory",
+                            target.display()
+                        )))
+                    },
+                }
+                continue
+            }
+
+            match existing {
+                | None => {
+                    move_entry(entry.path(), &target)?;
+                    trace!("'{}' +", target.display());
+                    self.changes.push(Change::Created(target));
+                },
+                | Some(m) if m.is_dir() => {
+                    return Err(io::Error::other(format!(
+                        "Refusing to replace directory '{}' with a file",
+                        target.display()
+                    )))
+                },
+                | Some(_) => {
+                    let backup = with_suffix(&target, BACKUP_SUFFIX);
+                    rename(&target, &backup)?;
+                    self.changes.push(Change::Replaced {
+                        target: target.clone(),
+                        backup,
+                    });
+                    move_entry(entry.path(), &target)?;
+                    trace!("'{}' +", target.display());
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// # Stops tracking created paths, so rolling back leaves them in place
+    ///
+    /// This is used for protected files, which hold the user's version again once settled.
+    pub fn keep<'a>(&mut self, paths: impl IntoIterator<Item = &'a Path>) {
+        let paths = paths.into_iter().collect::<Vec<_>>();
+        self.changes
+            .retain(|c| !matches!(c, Change::Created(p) if paths.contains(&p.as_path())));
+    }
+
+    /// # Removes backups and the staging directory, making the changes permanent
+    pub fn commit(self) {
+        for change in &self.changes {
+            if let Change::Replaced { backup, .. } = change
+                && let Err(e) = fs::remove_file(backup)
+            {
+                warn!("Failed to remove backup '{}': {e}", backup.display());
+            }
+        }
+    }
+
+    /// # Undoes every change in reverse, then restores the snapshotted data files
+    pub fn rollback(self) {
+        for change in self.changes.iter().rev() {
+            let result = match change {
+                | Change::Created(path) if path.symlink_metadata().is_ok_and(|m| m.is_dir()) => {
+                    fs::remove_dir_all(path)
+                },
+                | Change::Created(path) => fs::remove_file(path),
+                | Change::Replaced { target, backup } => rename(backup, target),
+            };
+
+            if let Err(e) = result {
+                error!("Failed to roll back {change:?}: {e}");
+            }
+        }
+
+        for (path, contents) in &self.snapshot {
+            let result = match contents {
+                | Some(c) => fs::write(path, c),
+                | None => fs::remove_file(path).or_else(|e| {
+                    if e.kind() == io::ErrorKind::NotFound { Ok(()) } else { Err(e) }
+                }),
+            };
+
+            if let Err(e) = result {
+                error!("Failed to restore '{}': {e}", path.display());
+            }
+        }
+    }
+}
+
+/// # Moves a staged file or symlink to its target
+///
+/// Renames fail with `CrossesDevices` when the target is on another filesystem, in which case the
+/// entry is copied instead.
+fn move_entry(src: &Path, target: &Path) -> io::Result<()> {
+    match rename(src, target) {
+        | Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            debug!("'{}' is on another filesystem, copying", target.display());
+            copy_entry(src, target)
+        },
+        | result => result,
+    }
+}
+
+/// # Copies a file or symlink to its target, then removes the source
+///
+/// The copy is made next to the target and renamed into place, so the target never holds a
+/// partial file. Ownership and permissions are kept.
+fn copy_entry(src: &Path, target: &Path) -> io::Result<()> {
+    let tmp = with_suffix(target, COPY_SUFFIX);
+    let copy = || {
+        let meta = src.symlink_metadata()?;
+        if meta.is_symlink() {
+            symlink(fs::read_link(src)?, &tmp)?;
+            lchown(&tmp, Some(meta.uid()), Some(meta.gid()))
+        } else {
+            fs::copy(src, &tmp)?;
+            // Changing the owner clears setuid and setgid, so permissions are set afterwards
+            lchown(&tmp, Some(meta.uid()), Some(meta.gid()))?;
+            fs::set_permissions(&tmp, meta.permissions())
+        }
+    };
+
+    if let Err(e) = copy().and_then(|()| rename(&tmp, target)) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e)
+    }
+
+    fs::remove_file(src)
+}
+
+/// # Creates a directory with the ownership and permissions of another
+fn create_dir_like(src: &Path, target: &Path) -> io::Result<()> {
+    let meta = src.metadata()?;
+    fs::create_dir(target)?;
+    lchown(target, Some(meta.uid()), Some(meta.gid()))?;
+    fs::set_permissions(target, meta.permissions())
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn stage_in(root: &Path, files: &[(&str, &str)]) -> Stage {
+        let dir = Builder::new().prefix(".to-stage-").tempdir_in(root).unwrap();
+        for (path, contents) in files {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        Stage {
+            dir,
+            root: root.to_path_buf(),
+            changes: Vec::new(),
+            snapshot: vec![(root.join("IV"), Some(b"1.0-1".to_vec()))],
+        }
+    }
+
+    #[test]
+    fn apply_and_rollback() {
+        let root = tempdir().unwrap();
+        let root = root.path();
+        fs::create_dir_all(root.join("usr/bin")).unwrap();
+        fs::write(root.join("usr/bin/foo"), "old").unwrap();
+        fs::write(root.join("IV"), "2.0-1").unwrap();
+
+        let mut stage = stage_in(root, &[
+            ("MANIFEST", "/usr/bin/foo\n"),
+            ("usr/bin/foo", "new"),
+            ("usr/share/foo/data", "data"),
+        ]);
+        stage.apply().unwrap();
+
+        assert_eq!(fs::read_to_string(root.join("usr/bin/foo")).unwrap(), "new");
+        assert!(root.join("usr/share/foo/data").exists());
+        assert!(!root.join("MANIFEST").exists());
+
+        stage.rollback();
+        assert_eq!(fs::read_to_string(root.join("usr/bin/foo")).unwrap(), "old");
+        assert_eq!(fs::read_to_string(root.join("IV")).unwrap(), "1.0-1");
+        assert!(!root.join("usr/share").exists());
+        assert!(!with_suffix(&root.join("usr/bin/foo"), BACKUP_SUFFIX).exists());
+    }
+
+    #[test]
+    fn commit_removes_backups() {
+        let root = tempdir().unwrap();
+        let root = root.path();
+        fs::write(root.join("foo"), "old").unwrap();
+
+        let mut stage = stage_in(root, &[("foo", "new")]);
+        stage.apply().unwrap();
+        stage.commit();
+
+        assert_eq!(fs::read_to_string(root.join("foo")).unwrap(), "new");
+        assert!(!with_suffix(&root.join("foo"), BACKUP_SUFFIX).exists());
+        // Only the root's own files remain, since the staging directory is dropped
+        assert_eq!(fs::read_dir(root).unwrap().count(), 1);
+    }
+
+    #[test]
+    /// The fallback for targets on another filesystem, which can't be forced in a test
+    fn copy_across_filesystems() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let dir = dir.path();
+        fs::write(dir.join("src"), "new").unwrap();
+        fs::set_permissions(dir.join("src"), fs::Permissions::from_mode(0o4755)).unwrap();
+        fs::write(dir.join("target"), "old").unwrap();
+        symlink("target", dir.join("link")).unwrap();
+
+        copy_entry(&dir.join("src"), &dir.join("target")).unwrap();
+        copy_entry(&dir.join("link"), &dir.join("copied-link")).unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("target")).unwrap(), "new");
+        let mode = dir.join("target").metadata().unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o4755);
+        assert_eq!(fs::read_link(dir.join("copied-link")).unwrap(), Path::new("target"));
+
+        // Only the copies remain
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["copied-link", "target"]);
+    }
+}
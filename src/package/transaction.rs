@@ -0,0 +1,406 @@
+// package/transaction.rs
+//! Install transactions
+//!
+//! Every requested package's dependency graph is merged into a single DAG, which is sorted once and
+//! classified into a plan. The plan can be previewed before anything is installed.
+
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    fmt,
+};
+
+use glob::Pattern;
+use indicatif::HumanBytes;
+use permitit::Permit;
+use petgraph::{
+    algo::toposort,
+    graph::{
+        DiGraph,
+        NodeIndex,
+    },
+};
+use tracing::{
+    debug,
+    error,
+};
+
+use super::{
+    FormError,
+    Package,
+    dep::DepKind,
+    install::InstallError,
+    reason::Reason,
+    trigger::run_triggers,
+};
+
+/// # What a transaction will do with a package
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    New,
+    Update,
+    Reinstall,
+    Installed,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Self::New => f.pad("new"),
+            | Self::Update => f.pad("update"),
+            | Self::Reinstall => f.pad("reinstall"),
+            | Self::Installed => f.pad("installed"),
+        }
+    }
+}
+
+impl Step {
+    /// # Classifies a package
+    ///
+    /// Up-to-date packages are only reinstalled if forced.
+    fn classify(pkg: &Package, force: bool) -> Self {
+        Self::from_state(pkg.is_installed(), pkg.is_current(), force)
+    }
+
+    /// # Classifies a package from whether it's installed and current
+    fn from_state(installed: bool, current: bool, force: bool) -> Self {
+        if !installed {
+            Self::New
+        } else if !current {
+            Self::Update
+        } else if force {
+            Self::Reinstall
+        } else {
+            Self::Installed
+        }
+    }
+}
+
+/// # A package in a transaction
+///
+/// # Fields
+/// * `step`            - What will be done with the package
+/// * `force`           - Whether the package is forcibly installed
+/// * `requested`       - Whether the package was requested, rather than pulled in as a dependency
+#[derive(Debug, Clone)]
+pub struct Planned {
+    pub pkg:       Package,
+    pub step:      Step,
+    pub force:     bool,
+    pub requested: bool,
+}
+
+/// # An install transaction, in dependency order
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    pub planned: Vec<Planned>,
+}
+
+impl Transaction {
+    /// # Plans the installation of several packages
+    ///
+    /// # Arguments
+    /// * `pkgs`            - The requested packages
+    /// * `force`           - Whether requested packages should be forcibly (re)installed
+    /// * `full_force`      - Whether dependencies should also be forcibly (re)installed
+    /// * `no_deps`         - Whether to leave out dependencies
+    ///
+    /// # Errors
+    /// - Will fail if a dependency could not be formed or doesn't satisfy its constraint
+    /// - Will fail if the dependencies form a cycle
+    pub fn plan(
+        pkgs: &[Package],
+        force: bool,
+        full_force: bool,
+        no_deps: bool,
+    ) -> Result<Self, FormError> {
+        let mut graph = DiGraph::<Package, ()>::new();
+        let mut index_map = HashMap::<String, NodeIndex>::new();
+
+        for pkg in pkgs {
+            if no_deps {
+                // Dependencies are cleared so `install_inner()` doesn't pull them in when run
+                index_map.entry(pkg.name.clone()).or_insert_with(|| {
+                    let mut pkg = pkg.clone();
+                    pkg.dependencies = vec![];
+                    graph.add_node(pkg)
+                });
+            } else {
+                pkg.build_dep_graph(&mut graph, &mut index_map, DepKind::for_install)?;
+            }
+        }
+
+        Self::from_graph(&graph, pkgs, force, full_force, Step::classify)
+    }
+
+    /// # Sorts and classifies a dependency graph into a plan
+    ///
+    /// # Errors
+    /// - Will fail if the graph contains a cycle
+    fn from_graph(
+        graph: &DiGraph<Package, ()>,
+        pkgs: &[Package],
+        force: bool,
+        full_force: bool,
+        classify: impl Fn(&Package, bool) -> Step,
+    ) -> Result<Self, FormError> {
+        let sorted = toposort(graph, None)
+            .map_err(|e| FormError::DependencyCycle(graph[e.node_id()].name.clone()))?;
+
+        let planned = sorted
+            .into_iter()
+            .map(|idx| {
+                let pkg = graph[idx].clone();
+                let requested = pkgs.iter().any(|p| p.name == pkg.name);
+                let force = full_force || (force && requested);
+                Planned {
+                    step: classify(&pkg, force),
+                    pkg,
+                    force,
+                    requested,
+                }
+            })
+            .collect();
+
+        Ok(Self { planned })
+    }
+
+    /// # Returns the packages that will be installed
+    pub fn pending(&self) -> impl Iterator<Item = &Planned> {
+        self.planned.iter().filter(|p| p.step != Step::Installed)
+    }
+
+    /// # Checks whether the transaction would do nothing
+    pub fn is_empty(&self) -> bool { self.pending().next().is_none() }
+
+    /// # Returns the pending packages missing a distfile
+    pub fn missing_distfiles(&self) -> Vec<Package> {
+        self.pending()
+            .filter(|p| !p.pkg.distfile().exists())
+            .map(|p| p.pkg.clone())
+            .collect()
+    }
+
+    /// # Prints the plan
+    ///
+    /// `sizes` holds the download sizes of missing distfiles, keyed by package name.
+    pub fn display(&self, sizes: &HashMap<String, u64>) {
+        for p in &self.planned {
+            let size = if p.step == Step::Installed || p.pkg.distfile().exists() {
+                String::new()
+            } else {
+                sizes
+                    .get(&p.pkg.name)
+                    .map_or("unknown size".to_string(), |s| HumanBytes(*s).to_string())
+            };
+
+            let dep = if p.requested { "" } else { " (dependency)" };
+            println!(
+                "  {:<10} {:<40} {size:>12}{dep}",
+                p.step,
+                format!("{:-}", p.pkg)
+            );
+        }
+
+        let total = sizes.values().sum::<u64>();
+        println!("Total download size: {}", HumanBytes(total));
+    }
+
+    /// # Runs the transaction
+    ///
+    /// Packages are installed in order, each exactly once. Files owned by other packages are only
+    /// overwritten if they match a pattern in `overwrite`.
+    ///
+    /// Requested packages are marked as explicitly installed as soon as they're installed, so a
+    /// later failure can't leave them marked as dependencies. Triggers activated by the installed
+    /// packages run once at the end.
+    ///
+    /// # Errors
+    /// - Will fail if any package fails to install, leaving later packages uninstalled
+    /// - Will fail if a requested package couldn't be marked
+    pub fn run(
+        &self,
+        suppress: bool,
+        root: Option<&str>,
+        overwrite: &[Pattern],
+    ) -> Result<(), InstallError> {
+        // Every planned package starts as visited, so `install_inner()` doesn't recurse into
+        // dependencies the transaction already orders before it
+        let mut visited = self
+            .planned
+            .iter()
+            .map(|p| p.pkg.name.clone())
+            .collect::<HashSet<_>>();
+
+        let mark = |p: &Planned| -> Result<(), InstallError> {
+            if p.requested && p.pkg.is_installed() {
+                p.pkg.mark(Reason::Explicit)?;
+            }
+            Ok(())
+        };
+
+        for p in self.planned.iter().filter(|p| p.step == Step::Installed) {
+            mark(p)?;
+        }
+
+        let mut installed = Vec::new();
+        let mut result = Ok(());
+        for p in self.pending() {
+            debug!("Running {} for {:-}", p.step, p.pkg);
+            visited.remove(&p.pkg.name);
+            let step = p
+                .pkg
+                .install_inner(p.force, false, &mut visited, suppress, root, overwrite)
+                .permit(|e| matches!(e, InstallError::AlreadyInstalled));
+            if let Err(e) = step.and(mark(p)) {
+                error!("Failed to install {:-}: {e}", p.pkg);
+                result = Err(e);
+                break
+            }
+            installed.push(&p.pkg);
+        }
+
+        // Triggers run even if the transaction failed partway, for the packages it did install
+        run_triggers(installed, root);
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn step_display() {
+        assert_eq!(format!("{:<10}|", Step::Reinstall), "reinstall |");
+        assert_eq!(Step::New.to_string(), "new");
+    }
+
+    /// Builds a graph where `app` depends on `lib`, and `lib` and `tool` depend on `base`
+    fn graph() -> (DiGraph<Package, ()>, Vec<Package>) {
+        let mut graph = DiGraph::new();
+        let [base, lib, app, tool] =
+            ["base", "lib", "app", "tool"].map(|n| graph.add_node(Package::stub(n, &[])));
+        for (from, to) in [(base, lib), (lib, app), (base, tool)] {
+            graph.add_edge(from, to, ());
+        }
+
+        let requested = vec![graph[app].clone(), graph[tool].clone()];
+        (graph, requested)
+    }
+
+    /// `base` is current, `lib` is outdated, and the rest aren't installed, except `tool` which is
+    /// current
+    fn classify(pkg: &Package, force: bool) -> Step {
+        match pkg.name.as_str() {
+            | "base" | "tool" => Step::from_state(true, true, force),
+            | "lib" => Step::from_state(true, false, force),
+            | _ => Step::from_state(false, false, force),
+        }
+    }
+
+    fn steps(tx: &Transaction) -> Vec<(&str, Step)> {
+        tx.planned
+            .iter()
+            .map(|p| (p.pkg.name.as_str(), p.step))
+            .collect()
+    }
+
+    #[test]
+    fn plan_steps_and_order() {
+        let (graph, requested) = graph();
+        let tx = Transaction::from_graph(&graph, &requested, false, false, classify).unwrap();
+
+        let position = |name| tx.planned.iter().position(|p| p.pkg.name == name).unwrap();
+        assert!(position("base") < position("lib"));
+        assert!(position("lib") < position("app"));
+        assert!(position("base") < position("tool"));
+
+        let mut steps = steps(&tx);
+        steps.sort_by_key(|(name, _)| *name);
+        assert_eq!(steps, [
+            ("app", Step::New),
+            ("base", Step::Installed),
+            ("lib", Step::Update),
+            ("tool", Step::Installed),
+        ]);
+
+        let requested = tx
+            .planned
+            .iter()
+            .filter(|p| p.requested)
+            .map(|p| p.pkg.name.as_str())
+            .collect::<HashSet<_>>();
+        assert_eq!(requested, HashSet::from(["app", "tool"]));
+
+        let pending = tx
+            .pending()
+            .map(|p| p.pkg.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(pending, ["lib", "app"]);
+        assert!(!tx.is_empty());
+    }
+
+    #[test]
+    fn plan_force() {
+        let (graph, requested) = graph();
+
+        // Forcing only reinstalls requested packages
+        let tx = Transaction::from_graph(&graph, &requested, true, false, classify).unwrap();
+        let forced = tx.planned.iter().filter(|p| p.force).count();
+        assert_eq!(forced, 2);
+        assert!(steps(&tx).contains(&("tool", Step::Reinstall)));
+        assert!(steps(&tx).contains(&("base", Step::Installed)));
+
+        // Fully forcing reinstalls dependencies too
+        let tx = Transaction::from_graph(&graph, &requested, false, true, classify).unwrap();
+        assert!(tx.planned.iter().all(|p| p.force));
+        assert!(steps(&tx).contains(&("base", Step::Reinstall)));
+        assert_eq!(tx.pending().count(), 4);
+    }
+
+    #[test]
+    fn plan_nothing_pending() {
+        let (graph, _) = graph();
+        let requested = vec![Package::stub("base", &[])];
+        let tx = Transaction::from_graph(&graph, &requested, false, false, |_, force| {
+            Step::from_state(true, true, force)
+        })
+        .unwrap();
+
+        assert!(tx.is_empty());
+        assert_eq!(tx.planned.len(), 4);
+    }
+
+    #[test]
+    fn plan_cycle() {
+        let mut graph = DiGraph::new();
+        let [a, b] = ["a", "b"].map(|n| graph.add_node(Package::stub(n, &[])));
+        graph.add_edge(a, b, ());
+        graph.add_edge(b, a, ());
+
+        let result = Transaction::from_graph(&graph, &[], false, false, classify);
+        assert!(matches!(result, Err(FormError::DependencyCycle(_))));
+    }
+
+    #[test]
+    /// A no-deps transaction must not hand its packages' dependencies to `install_inner()`
+    fn plan_no_deps() {
+        let pkg = Package::stub("to-test-app", &[
+            ("to-test-lib", DepKind::Required),
+            ("to-test-tool", DepKind::Runtime),
+        ]);
+        let tx = Transaction::plan(&[pkg.clone(), pkg], false, false, true).unwrap();
+
+        assert_eq!(tx.planned.len(), 1);
+        let p = &tx.planned[0];
+        assert_eq!(
+            (p.pkg.name.as_str(), p.step, p.requested),
+            ("to-test-app", Step::New, true)
+        );
+        assert!(p.pkg.dependencies.is_empty());
+        assert!(p.pkg.collect_install_deps().is_empty());
+    }
+}
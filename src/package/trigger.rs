@@ -0,0 +1,151 @@
+// package/trigger.rs
+//! Transaction-level triggers
+//!
+//! Triggers are defined in the config. Each names path globs, relative to the root, and a command
+//! to run when an installed package ships a matching path. Triggers are collected across every
+//! package in a transaction and each one runs once at the end. Commands receive the root as
+//! `$ROOT`.
+
+use std::{
+    fs::read_to_string,
+    path::Path,
+};
+
+use glob::Pattern;
+use serde::Deserialize;
+use tracing::{
+    debug,
+    info,
+    warn,
+};
+
+use super::{
+    Package,
+    manifest::path_of,
+};
+use crate::{
+    CONFIG,
+    exec,
+};
+
+/// # A trigger definition
+///
+/// # Fields
+/// * `name`            - A name for the trigger, used in logs
+/// * `paths`           - Globs for paths, relative to the root, that activate the trigger
+/// * `command`         - The command to run, with `$ROOT` set to the root
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct Trigger {
+    pub name:    String,
+    pub paths:   Vec<String>,
+    pub command: String,
+}
+
+impl Trigger {
+    fn new(name: &str, paths: &[&str], command: &str) -> Self {
+        Self {
+            name:    name.to_string(),
+            paths:   paths.iter().map(|p| p.to_string()).collect(),
+            command: command.to_string(),
+        }
+    }
+
+    /// # The triggers used when none are configured
+    pub fn defaults() -> Vec<Self> {
+        vec![
+            Self::new(
+                "ldconfig",
+                &["usr/lib/*.so*", "usr/lib64/*.so*", "etc/ld.so.conf*"],
+                r#"ldconfig -r "$ROOT""#,
+            ),
+            Self::new(
+                "icon-cache",
+                &["usr/share/icons/*"],
+                r#"for d in "$ROOT"/usr/share/icons/*/; do [ -f "$d/index.theme" ] && gtk-update-icon-cache -qf "$d"; done; true"#,
+            ),
+            Self::new(
+                "schemas",
+                &["usr/share/glib-2.0/schemas/*"],
+                r#"glib-compile-schemas "$ROOT/usr/share/glib-2.0/schemas""#,
+            ),
+            Self::new("fonts", &["usr/share/fonts/*"], r#"fc-cache -s -y "$ROOT""#),
+        ]
+    }
+
+    /// # Checks whether any of a set of paths activates the trigger
+    ///
+    /// Invalid globs are warned about and never match.
+    fn is_activated_by<'a>(&self, paths: impl IntoIterator<Item = &'a str> + Clone) -> bool {
+        self.paths.iter().any(|g| {
+            let Ok(pattern) = Pattern::new(g.trim_start_matches('/')) else {
+                warn!("Invalid path glob '{g}' for trigger {}", self.name);
+                return false
+            };
+            paths.clone().into_iter().any(|p| pattern.matches(p))
+        })
+    }
+
+    /// # Runs the trigger against a root
+    fn run(&self, root: &Path) {
+        info!("Running trigger {}", self.name);
+        if let Err(e) = exec!("export ROOT={root:?}\n{}", self.command) {
+            warn!("Trigger {} failed: {e}", self.name);
+        }
+    }
+}
+
+/// # Finds the triggers activated by a set of paths
+fn activated<'a>(triggers: &'a [Trigger], paths: &[String]) -> Vec<&'a Trigger> {
+    triggers
+        .iter()
+        .filter(|t| t.is_activated_by(paths.iter().map(String::as_str)))
+        .collect()
+}
+
+/// # Runs the triggers activated by packages' manifests, each once
+///
+/// Trigger failures are warned about, since the packages are already installed.
+pub fn run_triggers<'a>(pkgs: impl IntoIterator<Item = &'a Package>, root: Option<&str>) {
+    let paths = pkgs
+        .into_iter()
+        .filter_map(|p| {
+            let manifest = p
+                .datadir()
+                .join(format!("MANIFEST@{}", p.version.srversion()));
+            read_to_string(&manifest)
+                .inspect_err(|e| warn!("Failed to read {} for triggers: {e}", manifest.display()))
+                .ok()
+        })
+        .flat_map(|m| m.lines().map(|l| path_of(l).to_string()).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    let root = Path::new(root.unwrap_or("/"));
+    for trigger in activated(&CONFIG.triggers, &paths) {
+        debug!("Trigger {} activated", trigger.name);
+        trigger.run(root);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn activation() {
+        let triggers = Trigger::defaults();
+        let paths = [
+            "usr",
+            "usr/lib",
+            "usr/lib/libz.so.1.3",
+            "usr/share/icons/hicolor/48x48/apps/foo.png",
+        ]
+        .map(String::from);
+
+        let names = activated(&triggers, &paths)
+            .iter()
+            .map(|t| t.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["ldconfig", "icon-cache"]);
+        assert!(activated(&triggers, &["usr/bin/foo".to_string()]).is_empty());
+    }
+}
@@ -0,0 +1,191 @@
+// package/version.rs
+//! Version comparison
+//!
+//! Versions are split into segments on non-alphanumeric characters, and further into runs of
+//! digits and letters, which are compared pairwise. This handles dotted semver (`1.10.0` >
+//! `1.9.2`), date versions (`20250101`, `2025.01.01`), pre-releases (`1.0rc1` < `1.0`), and
+//! letter suffixes (`1.1.1a` > `1.1.1`).
+//!
+//! Commit hashes can't be ordered by their contents, so comparisons involving them are
+//! considered incomparable by `Version::compare()` unless the hashes are identical.
+
+use std::cmp::Ordering;
+
+use super::Version;
+use crate::utils::parse::is_commit_sha;
+
+/// Alphabetic segments that mark a pre-release, ordered from least to most mature
+const PRERELEASE: &[&str] = &["dev", "alpha", "beta", "pre", "rc"];
+
+#[derive(Debug, PartialEq, Eq)]
+enum Segment<'a> {
+    Number(&'a str),
+    Alpha(&'a str),
+}
+
+impl Segment<'_> {
+    /// Ranks segments by kind, with the end of a version between pre-releases and suffixes
+    fn class(segment: Option<&Self>) -> u8 {
+        match segment {
+            | Some(Segment::Alpha(a)) if PRERELEASE.contains(a) => 0,
+            | None => 1,
+            | Some(Segment::Alpha(_)) => 2,
+            | Some(Segment::Number(_)) => 3,
+        }
+    }
+
+    fn cmp_opt(a: Option<&Self>, b: Option<&Self>) -> Ordering {
+        match (a, b) {
+            | (Some(Segment::Number(x)), Some(Segment::Number(y))) => {
+                let x = x.trim_start_matches('0');
+                let y = y.trim_start_matches('0');
+                x.len().cmp(&y.len()).then_with(|| x.cmp(y))
+            },
+            | (Some(Segment::Alpha(x)), Some(Segment::Alpha(y)))
+                if PRERELEASE.contains(x) && PRERELEASE.contains(y) =>
+            {
+                let rank = |s| PRERELEASE.iter().position(|p| *p == s);
+                rank(*x).cmp(&rank(*y))
+            },
+            | (Some(Segment::Alpha(x)), Some(Segment::Alpha(y)))
+                if !PRERELEASE.contains(x) && !PRERELEASE.contains(y) =>
+            {
+                x.cmp(y)
+            },
+            | _ => Self::class(a).cmp(&Self::class(b)),
+        }
+    }
+}
+
+fn segments(s: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+
+    for part in s.split(|c: char| !c.is_ascii_alphanumeric()) {
+        let mut rest = part;
+        while let Some(first) = rest.chars().next() {
+            let digit = first.is_ascii_digit();
+            let end = rest
+                .find(|c: char| c.is_ascii_digit() != digit)
+                .unwrap_or(rest.len());
+            let (run, tail) = rest.split_at(end);
+            segments.push(if digit { Segment::Number(run) } else { Segment::Alpha(run) });
+            rest = tail;
+        }
+    }
+
+    segments
+}
+
+/// # Compares two version strings, without releases
+///
+/// Returns `None` if the versions differ and either is a commit hash.
+pub fn compare_versions(a: &str, b: &str) -> Option<Ordering> {
+    if a == b {
+        return Some(Ordering::Equal)
+    }
+
+    if is_commit_sha(a) || is_commit_sha(b) {
+        return None
+    }
+
+    let (a, b) = (a.to_ascii_lowercase(), b.to_ascii_lowercase());
+    let (a, b) = (segments(&a), segments(&b));
+
+    let len = a.len().max(b.len());
+    Some(
+        (0..len)
+            .map(|i| Segment::cmp_opt(a.get(i), b.get(i)))
+            .find(|o| o.is_ne())
+            .unwrap_or(Ordering::Equal),
+    )
+}
+
+impl Version {
+    /// # Compares two versions, including their releases
+    ///
+    /// Returns `None` if the versions can't be meaningfully ordered, which happens when they
+    /// differ and either is a commit hash. Callers should handle this explicitly, usually by
+    /// treating the versions as different without assuming a direction.
+    pub fn compare(&self, other: &Self) -> Option<Ordering> {
+        compare_versions(&self.version, &other.version).map(|o| o.then(self.release.cmp(&other.release)))
+    }
+}
+
+/// # A total order on versions
+///
+/// This agrees with `Version::compare()` wherever it returns `Some`. Since `Ord` must be total,
+/// commit hashes are ordered before all other versions, and among themselves by their contents.
+/// That ordering is only useful for sorting, not for telling upgrades from downgrades.
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (a, b) = (is_commit_sha(&self.version), is_commit_sha(&other.version));
+        match (a, b) {
+            | (true, false) => Ordering::Less,
+            | (false, true) => Ordering::Greater,
+            | (true, true) => self
+                .version
+                .cmp(&other.version)
+                .then(self.release.cmp(&other.release)),
+            | (false, false) => self
+                .compare(other)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| self.version.cmp(&other.version)),
+        }
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cmp::Ordering::*;
+
+    use super::*;
+
+    fn v(s: &str) -> Version { s.parse().unwrap() }
+
+    #[test]
+    fn semver() {
+        assert!(v("1.10.0-1") > v("1.9.2-1"));
+        assert!(v("2.40-1") > v("2.4-1"));
+        assert!(v("1.0.1-1") > v("1.0-1"));
+        assert!(v("1.2.3-2") > v("1.2.3-1"));
+        assert_eq!(v("1.02-1").compare(&v("1.2-1")), Some(Equal));
+        assert_ne!(v("1.02-1").cmp(&v("1.2-1")), Equal);
+    }
+
+    #[test]
+    fn prereleases_and_suffixes() {
+        assert!(v("1.0rc1-1") < v("1.0-1"));
+        assert!(v("1.0-rc2-1") > v("1.0-rc1-1"));
+        assert!(v("1.0beta-1") < v("1.0rc1-1"));
+        assert!(v("1.1.1a-1") > v("1.1.1-1"));
+        assert!(v("1.1.1b-1") > v("1.1.1a-1"));
+    }
+
+    #[test]
+    fn dates() {
+        assert!(v("20250225-1") > v("20241231-1"));
+        assert!(v("2025.02.25-1") > v("2024.12.31-1"));
+    }
+
+    #[test]
+    fn commit_hashes() {
+        let a = v("3e53eef5bff5e87804ba2f27f8d82d8f55b68d16-1");
+        let b = v("0a53eef5bff5e87804ba2f27f8d82d8f55b68d16-1");
+
+        assert_eq!(a.compare(&b), None);
+        assert_eq!(a.compare(&v("1.0-1")), None);
+        assert_eq!(a.compare(&a), Some(Equal));
+        assert_eq!(
+            a.compare(&v("3e53eef5bff5e87804ba2f27f8d82d8f55b68d16-2")),
+            Some(Less)
+        );
+
+        // Total order for sorting
+        assert!(a < v("1.0-1"));
+        assert!(b < a);
+    }
+}
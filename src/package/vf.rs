@@ -2,6 +2,7 @@
 //! Functions for fetching package upstreams
 
 use std::{
+    cmp::Ordering,
     fs::{
         read_to_string,
         write,
@@ -32,7 +33,10 @@ use tracing::{
 };
 
 use crate::{
-    package::Package,
+    package::{
+        Package,
+        version::compare_versions,
+    },
     sex,
     utils::{
         commit_hash::try_shorten,
@@ -171,15 +175,20 @@ pub struct Vf {
     /// Upstream Version
     pub uv:         String,
     pub is_current: bool,
+    /// Whether the local version is newer than the upstream version
+    #[serde(default)]
+    pub is_ahead:   bool,
 }
 
 impl Vf {
     fn new(n: &str, v: &str, uv: &str) -> Self {
+        let ord = compare_versions(v, uv);
         Self {
             n:          n.to_string(),
             v:          v.to_string(),
             uv:         uv.to_string(),
-            is_current: v == uv,
+            is_current: v == uv || ord == Some(Ordering::Equal),
+            is_ahead:   ord == Some(Ordering::Greater),
         }
     }
 
@@ -191,6 +200,8 @@ impl Vf {
 
         if self.is_current {
             println!("\x1b[37;1m[\x1b[32m*\x1b[37m]\x1b[0m \x1b[32m{n:<32}\x1b[0m {v} ~ {uv}");
+        } else if self.is_ahead {
+            println!("\x1b[37;1m[\x1b[33m+\x1b[37m]\x1b[0m \x1b[33m{n:<32}\x1b[0m {v} ~ {uv}");
         } else {
             println!("\x1b[37;1m[\x1b[31m-\x1b[37m]\x1b[0m \x1b[31m{n:<32}\x1b[0m {v} ~ {uv}");
         }
@@ -9,9 +9,14 @@ use fshelpers::{
     mkdir_p,
     rmdir_r,
 };
+use indicatif::HumanBytes;
 use tracing::error;
 
-use super::Package;
+use super::{
+    Package,
+    dep::DepKind,
+    provides::providers_of,
+};
 use crate::{
     exec,
     sex,
@@ -25,7 +30,7 @@ impl Package {
     /// - 1 => 0, about
     /// - 2 => 1, tags, licenses
     /// - 3 => 2, dependencies, kcfg
-    /// - 4 => 3, upstream, sources, distfile, maintainer
+    /// - 4 => 3, upstream, sources, distfile, sizes, maintainer
     pub fn view(&self, detail: u8) {
         // TODO: Format this with [*] name@version instead
         println!("{self:+}");
@@ -52,20 +57,28 @@ impl Package {
             return;
         }
 
-        let deps = if self.dependencies.is_empty() {
+        let (optional, deps) = self
+            .dependencies
+            .iter()
+            .partition::<Vec<_>, _>(|d| d.kind == DepKind::Optional);
+
+        let deps = if deps.is_empty() {
             "None"
         } else {
-            &self
-                .dependencies
-                .iter()
-                .map(|d| d.to_string())
-                .collect::<Vec<_>>()
-                .join("\n - ")
+            &deps.iter().map(|d| format!("{d:+}")).collect::<Vec<_>>().join("\n - ")
         };
 
         let kcfg = if self.kcfg.is_empty() { "None" } else { &self.kcfg.join("\n - ") };
 
+        if !self.provides.is_empty() {
+            println!("\n \x1b[1mProvides:\x1b[0;3m {}\x1b[0m", self.provides.join(", "));
+        }
+
         println!("\n󰪴 \x1b[1mDependencies:\n\x1b[0;3m - {deps}\x1b[0m");
+        if !optional.is_empty() {
+            let optional = optional.iter().map(|d| d.to_string()).collect::<Vec<_>>().join("\n - ");
+            println!("󰪴 \x1b[1mOptional dependencies:\n\x1b[0;3m - {optional}\x1b[0m");
+        }
         println!(" \x1b[1mKernel config options:\n\x1b[0;3m - {kcfg}\x1b[0m");
 
         if detail == 3 {
@@ -82,12 +95,59 @@ impl Package {
         let distfile = &self.distfile();
         let pkgfile = &self.pkgfile();
 
+        // Installed packages show the sizes of their installed version
+        let sizes = if self.is_installed() { self.installed_sizes() } else { self.distfile_sizes() };
+        let installed = sizes.map_or("Unknown installed size".to_string(), |s| {
+            format!("{} installed, {} files", HumanBytes(s.installed), s.files)
+        });
+        let distfile_size = sizes
+            .and_then(|s| s.distfile)
+            .or_else(|| distfile.metadata().ok().map(|m| m.len()))
+            .map(|s| format!(" ({})", HumanBytes(s)))
+            .unwrap_or_default();
+
         println!("\n󰘬 \x1b[3m{upstream}\x1b[0m");
         println!(" \x1b[3m{sources}\x1b[0m");
-        println!("󰏗 \x1b[3m{}\x1b[0m", distfile.display());
+        println!("󰏗 \x1b[3m{}{distfile_size}\x1b[0m", distfile.display());
+        println!("󰋊 \x1b[3m{installed}\x1b[0m");
         println!(" \x1b[3m{}\x1b[0m", pkgfile.display());
     }
 
+    /// # Lists the providers of a virtual package
+    pub fn view_providers(name: &str) {
+        let providers = providers_of(name);
+        if providers.is_empty() {
+            error!("Nothing provides {name}");
+            exit(1);
+        }
+
+        println!(" \x1b[1m{name} is provided by:\x1b[0m");
+        for pkg in providers {
+            println!("{pkg:+}");
+        }
+    }
+
+    pub fn view_deep_dependants(&self, kinds: &[DepKind], installed_only: bool) {
+        let filter = |k: DepKind| kinds.is_empty() || kinds.contains(&k);
+        let deps = &self.deep_dependants(filter, installed_only).unwrap_or_else(|e| {
+            error!("Failed to form one or more packages: {e}");
+            exit(1);
+        });
+
+        if deps.is_empty() {
+            println!("Nothing depends on {self}");
+            return;
+        }
+
+        println!("󰪴 \x1b[1mDeep dependants:\x1b[0m");
+        for (dep, chain) in deps {
+            let kind = dep.depkind.unwrap();
+            let chain = chain.join(" -> ");
+            let dep = format!("{dep:+}");
+            println!("{dep:<48} ({kind}) \x1b[3mvia {chain}\x1b[0m");
+        }
+    }
+
     pub fn view_dependants(&self) {
         let deps = &self.dependants().unwrap_or_else(|e| {
             error!("Failed to form one or more packages: {e}");
@@ -131,7 +191,8 @@ impl Package {
             --keep-directory-symlink    \
             --numeric-owner             \
             --no-overwrite-dir          \
-            --exclude=MANIFEST
+            --exclude=MANIFEST          \
+            --exclude=SIZE
             ",
             distfile = self.distfile().to_string_lossy()
         )?;
@@ -12,7 +12,10 @@ use std::{
         Command,
         Stdio,
     },
-    thread,
+    thread::{
+        self,
+        JoinHandle,
+    },
 };
 
 use tracing::{
@@ -65,32 +68,8 @@ pub fn exec(command: &str) -> io::Result<()> {
         .stderr(Stdio::piped())
         .spawn()?;
 
-    let stdout = child.stdout.take().unwrap();
-    let stderr = child.stderr.take().unwrap();
-
-    let stdout_thread = thread::spawn(move || {
-        let reader = io::BufReader::new(stdout);
-        for line in reader.lines() {
-            match line {
-                | Ok(line) => {
-                    trace!(" [STDOUT] {line}");
-                },
-                | Err(e) => error!("Error reading stdout: {e}"),
-            }
-        }
-    });
-
-    let stderr_thread = thread::spawn(move || {
-        let reader = io::BufReader::new(stderr);
-        for line in reader.lines() {
-            match line {
-                | Ok(line) => {
-                    debug!(" [STDERR] {line}");
-                },
-                | Err(e) => error!("Error reading stderr: {e}"),
-            }
-        }
-    });
+    let stdout_thread = log_lines(child.stdout.take().unwrap(), false);
+    let stderr_thread = log_lines(child.stderr.take().unwrap(), true);
 
     let status = child.wait()?;
     if !status.success() {
@@ -106,6 +85,21 @@ pub fn exec(command: &str) -> io::Result<()> {
     Ok(())
 }
 
+/// # Logs each line of a child's stdout or stderr from a separate thread
+pub fn log_lines(stream: impl io::Read + Send + 'static, stderr: bool) -> JoinHandle<()> {
+    thread::spawn(move || {
+        for line in io::BufReader::new(stream).lines() {
+            match line {
+                | Ok(line) if stderr => debug!(" [STDERR] {line}"),
+                | Ok(line) => trace!(" [STDOUT] {line}"),
+                | Err(e) => {
+                    error!("Error reading {}: {e}", if stderr { "stderr" } else { "stdout" })
+                },
+            }
+        }
+    })
+}
+
 pub fn exec_interactive(command: &str) -> io::Result<()> {
     let command = prepend_source_base(command);
 
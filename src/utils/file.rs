@@ -2,7 +2,10 @@
 //! File-related utilities
 
 use std::{
-    fs::OpenOptions as OO,
+    fs::{
+        File,
+        OpenOptions as OO,
+    },
     io::{
         self,
         Write,
@@ -12,6 +15,10 @@ use std::{
 };
 
 use fshelpers::mkdir_p;
+use sha2::{
+    Digest,
+    Sha256,
+};
 
 // This is kinda fucking stupid but I really don't have a better way to check just given a string.
 // This is currently being kept as I may handle tarballs and zips in-house eventually.
@@ -163,3 +170,17 @@ pub fn mtime<P: AsRef<Path>>(path: P) -> Option<SystemTime> {
         .ok()
         .and_then(|m| m.modified().ok())
 }
+
+/// # Get the hex-encoded SHA-256 hash of a file's contents
+///
+/// # Arguments
+/// * `path`        - The file to hash.
+///
+/// # Errors
+/// Returns an `io::Error` if:
+/// - The file could not be opened or read.
+pub fn sha256<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
@@ -1,6 +1,7 @@
 // utils/health.rs
 
 use tracing::{error, warn, info, debug};
+use crate::package::mounts::{Holder, clean_stale_mounts, stale_mounts};
 use crate::utils::file::exists;
 
 #[derive(Copy, Clone)]
@@ -61,9 +62,50 @@ const TODEPS: &[(ToDepKind, &str, &str)] = &[
     (ToDepKind::Recommended, "env",    "Cannot build packages"),
 ];
 
+/// Checks for mounts left behind by a killed build, unmounting them
+///
+/// Returns the number of stale mounts that couldn't be unmounted
+fn check_stale_mounts() -> u8 {
+    debug!("Checking for stale build overlay mounts");
+    let stale = match stale_mounts() {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("Failed to check for stale mounts: {e}");
+            return 1
+        },
+    };
+
+    if stale.is_empty() {
+        return 0
+    }
+
+    warn!("Found {} stale mount(s) from an interrupted build", stale.len());
+
+    // A build may have started since the check
+    let Ok(holder) = Holder::acquire() else {
+        warn!("Not unmounting stale mounts while the build overlay is in use");
+        return 0
+    };
+
+    match clean_stale_mounts(&holder) {
+        Ok(0) => {
+            info!("Unmounted stale mounts");
+            0
+        },
+        Ok(n) => {
+            error!("Failed to unmount {n} stale mount(s)");
+            n.try_into().unwrap_or(u8::MAX)
+        },
+        Err(e) => {
+            error!("Failed to unmount stale mounts: {e}");
+            1
+        },
+    }
+}
+
 pub fn check_health() -> u8 {
     info!("Checking health");
-    let mut n = 0;
+    let mut n: u8 = 0;
 
     for dep in TODEPS.iter().map(|v| ToDep::new(v.0, v.1, v.2)) {
         if matches!(dep.kind, ToDepKind::Recommended | ToDepKind::Required) && !dep.check() {
@@ -77,5 +119,5 @@ pub fn check_health() -> u8 {
         warn!("Missing some dependencies")
     }
 
-    n
+    n.saturating_add(check_stale_mounts())
 }
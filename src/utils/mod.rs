@@ -4,4 +4,5 @@ pub mod exec;
 pub mod file;
 pub mod health;
 pub mod parse;
+pub mod prompt;
 pub mod log;
@@ -0,0 +1,43 @@
+// utils/prompt.rs
+//! Utilities for prompting the user
+
+use std::io::{
+    self,
+    Write,
+};
+
+/// # Prompts for a single character answer
+///
+/// An empty answer returns `default`, as does EOF.
+pub fn prompt(message: &str, default: char) -> io::Result<char> {
+    Ok(read_answer(message, default)?.unwrap_or(default))
+}
+
+/// # Prompts for a yes or no answer, defaulting to yes
+///
+/// Only an empty answer accepts the default. EOF, such as from a closed or empty stdin, answers no,
+/// so nothing is approved without someone there to approve it.
+pub fn confirm(message: &str) -> io::Result<bool> {
+    Ok(read_answer(&format!("{message} [Y/n]"), 'y')? == Some('y'))
+}
+
+/// # Reads a single character answer, returning `None` at EOF
+fn read_answer(message: &str, default: char) -> io::Result<Option<char>> {
+    print!("{message} ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer)? == 0 {
+        println!();
+        return Ok(None)
+    }
+
+    Ok(Some(
+        answer
+            .trim()
+            .chars()
+            .next()
+            .unwrap_or(default)
+            .to_ascii_lowercase(),
+    ))
+}